@@ -0,0 +1,15 @@
+//! Decides if the borrow checks are dropped.
+//!
+//! They are only dropped with `--cfg syncell_unchecked` in `RUSTFLAGS`,
+//! in the builds without debug assertions, and never under Loom.
+//! The crate itself only looks at the resulting `unchecked` cfg.
+
+use std::env;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    let has_cfg = |name: &str| env::var_os(format!("CARGO_CFG_{}", name)).is_some();
+    if has_cfg("SYNCELL_UNCHECKED") && !has_cfg("DEBUG_ASSERTIONS") && !has_cfg("LOOM") {
+        println!("cargo:rustc-cfg=unchecked");
+    }
+}
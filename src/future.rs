@@ -0,0 +1,88 @@
+//! Asynchronous borrowing with "async" feature.
+//!
+//! The futures try to borrow the cell when polled, and if it's not possible,
+//! register the task waker to be woken up when the conflicting borrow is released.
+
+use super::{BorrowKind, SynCell, SynRef, SynRefMut};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A future returned by [`SynCell::borrow_async`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct BorrowFuture<'a, T: ?Sized> {
+    pub(super) cell: &'a SynCell<T>,
+}
+
+impl<'a, T: ?Sized> Future for BorrowFuture<'a, T> {
+    type Output = SynRef<'a, T>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        loop {
+            if let Ok(r) = self.cell.try_borrow() {
+                return Poll::Ready(r);
+            }
+            if self.cell.state.register(BorrowKind::Shared, cx.waker()) {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+/// A future returned by [`SynCell::borrow_mut_async`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct BorrowMutFuture<'a, T: ?Sized> {
+    pub(super) cell: &'a SynCell<T>,
+}
+
+impl<'a, T: ?Sized> Future for BorrowMutFuture<'a, T> {
+    type Output = SynRefMut<'a, T>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        loop {
+            if let Ok(r) = self.cell.try_borrow_mut() {
+                return Poll::Ready(r);
+            }
+            if self.cell.state.register(BorrowKind::Exclusive, cx.waker()) {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+struct FlagWaker(std::sync::atomic::AtomicBool);
+
+#[cfg(test)]
+impl std::task::Wake for FlagWaker {
+    fn wake(self: std::sync::Arc<Self>) {
+        self.0.store(true, std::sync::atomic::Ordering::Release);
+    }
+}
+
+#[test]
+fn wake_on_release() {
+    use std::sync::{atomic::Ordering, Arc};
+    let flag = Arc::new(FlagWaker(Default::default()));
+    let waker = Arc::clone(&flag).into();
+    let mut cx = Context::from_waker(&waker);
+
+    let sc = SynCell::new(0u8);
+    let bw = sc.borrow_mut();
+    let mut fut = sc.borrow_async();
+    assert!(Pin::new(&mut fut).poll(&mut cx).is_pending());
+    assert!(!flag.0.load(Ordering::Acquire));
+    drop(bw);
+    assert!(flag.0.load(Ordering::Acquire));
+    let b = match Pin::new(&mut fut).poll(&mut cx) {
+        Poll::Ready(b) => b,
+        Poll::Pending => panic!("still pending"),
+    };
+
+    flag.0.store(false, Ordering::Release);
+    let mut fut_mut = sc.borrow_mut_async();
+    assert!(Pin::new(&mut fut_mut).poll(&mut cx).is_pending());
+    drop(b);
+    assert!(flag.0.load(Ordering::Acquire));
+    assert!(Pin::new(&mut fut_mut).poll(&mut cx).is_ready());
+}
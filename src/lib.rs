@@ -3,73 +3,696 @@
 //! Main principles:
 //!   1. if you change state, and it's fine, you reverse it on drop()
 //!   2. if you found a problem, still undo your change, and then panic()
+//!
+//! `SynCell` supports unsized values, e.g. `&SynCell<[u8; 4]>` coerces to `&SynCell<[u8]>`.
+//! With "nightly" feature enabled, the cell and its guards also implement `CoerceUnsized`.
+//!
+//! With "async" feature enabled, the cell can be borrowed asynchronously,
+//! waiting for the conflicting borrows to be released.
+//!
+//! With "debug-tracking" feature enabled, the cell remembers the locations and threads
+//! of the live mutable and upgradable borrows, and reports them when a conflict is found.
+//! Shared borrows can't be pinned down to one site, so they are not reported.
+//!
+//! With "metrics" feature enabled, the cell counts its borrows and conflicts,
+//! which helps finding the contended cells.
+//!
+//! With "version" feature enabled, the cell counts its mutable borrows,
+//! so that the readers can detect if the value has changed since they last saw it.
+//!
+//! With "reentrancy" feature enabled, the cell remembers the thread holding
+//! the mutable borrow, so the conflict errors tell if it's the same thread,
+//! and the thread can nest the mutable borrows with `borrow_mut_reentrant`.
+//!
+//! With `--cfg syncell_unchecked` passed in `RUSTFLAGS`, the builds without
+//! debug assertions drop the borrow state of `SynCell` and `SynCellSlice`:
+//! the panicking borrows don't check anything, and the guards are just
+//! pointers to the value. Like `portable_atomic_unsafe_assume_single_core`,
+//! it's a promise made by the final binary: the debug builds, which keep
+//! all the checks, have to show that these borrows never panic.
+//! Everything that needs to know the actual state is not available then:
+//! the `try_*` borrows, the blocking, timed, and asynchronous borrows,
+//! `SynRefUpgradable::try_upgrade`, `borrow_state`, and `SynRwCell`.
+//! `Debug` prints `<unchecked>` instead of the value, the poison is never set,
+//! and the metrics stay at zero. The version is still counted.
+//! `RawSynLock`, `SynOnceCell`, and `SynLazy` are not affected.
+//!
+//! The crate is `no_std` unless "std" feature is enabled, which is needed for
+//! the blocking borrows, and implied by "poison", "debug-tracking", and "async".
+//! Targets without native atomics can be supported with "portable-atomic" feature.
+#![cfg_attr(not(any(feature = "std", test, loom)), no_std)]
+#![cfg_attr(feature = "nightly", feature(coerce_unsized, unsize))]
 
+extern crate alloc;
+
+#[cfg(all(feature = "async", not(loom), not(unchecked)))]
+mod future;
+#[cfg(all(feature = "lock_api", not(loom)))]
+mod lock;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod multi;
+mod once;
+#[cfg(target_has_atomic = "ptr")]
+mod owned;
+#[cfg(all(feature = "std", not(loom)))]
+mod park;
+#[cfg(all(feature = "std", not(loom), not(unchecked)))]
+mod rw;
+#[cfg(feature = "serde")]
+mod serde;
+mod slice;
+// Still needed by `RawSynLock`, which has to actually lock.
+#[cfg_attr(unchecked, allow(dead_code))]
+mod state;
+#[cfg(feature = "debug-tracking")]
+mod track;
+#[cfg(unchecked)]
+mod unchecked;
+
+#[cfg(all(not(loom), not(feature = "portable-atomic")))]
+use core::sync::atomic;
 #[cfg(loom)]
-use loom as mystd;
-#[cfg(not(loom))]
-use std as mystd;
+use loom::sync::atomic;
+#[cfg(all(not(loom), feature = "portable-atomic"))]
+use portable_atomic as atomic;
 
-use mystd::{
+#[cfg(all(feature = "async", not(loom), not(unchecked)))]
+pub use future::{BorrowFuture, BorrowMutFuture};
+#[cfg(all(feature = "lock_api", not(loom)))]
+pub use lock::RawSynLock;
+#[cfg(feature = "metrics")]
+pub use metrics::Metrics;
+pub use multi::{borrow_mut_many, borrow_mut_pair};
+#[cfg(not(unchecked))]
+pub use multi::{try_borrow_mut_many, try_borrow_mut_pair};
+pub use once::{SynLazy, SynOnceCell};
+#[cfg(target_has_atomic = "ptr")]
+pub use owned::{SynRefArc, SynRefMutArc};
+#[cfg(all(feature = "std", not(loom), not(unchecked)))]
+pub use rw::{Fairness, SynRwCell};
+pub use slice::SynCellSlice;
+use state::Panicking;
+#[cfg(not(unchecked))]
+use state::{State, StateRef};
+#[cfg(all(feature = "std", not(loom), not(unchecked)))]
+use std::time::{Duration, Instant};
+#[cfg(unchecked)]
+use unchecked::{State, StateRef};
+// Note: the value cell is not tracked by Loom, since the guards
+// need to hold on to the pointers. Loom still checks the state transitions.
+use core::{
     cell::UnsafeCell,
-    sync::atomic::{AtomicUsize, Ordering},
+    cmp,
+    error::Error,
+    fmt, hash,
+    marker::PhantomData,
+    mem, ops,
+    ptr::{self, NonNull},
 };
-use std::{mem, ops};
 
-const WRITE_BIT: usize = 1 << (mem::size_of::<usize>() * 8 - 1);
+/// Kind of a borrow.
+#[derive(Clone, Copy, Debug)]
+enum BorrowKind {
+    Shared,
+    Upgradable,
+    Exclusive,
+}
+
+/// Current state of the borrows, as reported by [`SynCell::borrow_state`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BorrowState {
+    /// Not borrowed.
+    Unused,
+    /// Borrowed immutably, possibly with an upgradable borrow.
+    Reading { readers: usize, upgradable: bool },
+    /// Borrowed mutably.
+    Writing,
+}
+
+/// An error returned by [`SynCell::try_borrow`].
+#[derive(Debug)]
+pub struct BorrowError {
+    #[cfg(feature = "debug-tracking")]
+    site: Option<track::Site>,
+    #[cfg(all(feature = "reentrancy", not(loom)))]
+    reentrant: bool,
+    _private: (),
+}
+
+#[cfg(all(feature = "reentrancy", not(loom)))]
+fn write_owner(f: &mut fmt::Formatter, reentrant: bool) -> fmt::Result {
+    f.write_str(if reentrant {
+        " (reentrant borrow on the same thread)"
+    } else {
+        " (borrowed by another thread)"
+    })
+}
+
+impl fmt::Display for BorrowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SynCell is mutably borrowed elsewhere!")?;
+        #[cfg(all(feature = "reentrancy", not(loom)))]
+        write_owner(f, self.reentrant)?;
+        #[cfg(feature = "debug-tracking")]
+        if let Some(ref site) = self.site {
+            write!(f, " (at {})", site)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for BorrowError {}
+
+/// An error returned by [`SynCell::try_borrow_mut`] and [`SynCell::try_borrow_upgradable`].
+#[derive(Debug)]
+pub struct BorrowMutError {
+    conflict: BorrowKind,
+    #[cfg(feature = "debug-tracking")]
+    site: Option<track::Site>,
+    /// Only known for the mutable conflicts.
+    #[cfg(all(feature = "reentrancy", not(loom)))]
+    reentrant: Option<bool>,
+}
+
+impl fmt::Display for BorrowMutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self.conflict {
+            BorrowKind::Shared => "SynCell is immutably borrowed elsewhere!",
+            BorrowKind::Upgradable => "SynCell is upgradably borrowed elsewhere!",
+            BorrowKind::Exclusive => "SynCell is mutably borrowed elsewhere!",
+        })?;
+        #[cfg(all(feature = "reentrancy", not(loom)))]
+        if let Some(reentrant) = self.reentrant {
+            write_owner(f, reentrant)?;
+        }
+        #[cfg(feature = "debug-tracking")]
+        if let Some(ref site) = self.site {
+            write!(f, " (at {})", site)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for BorrowMutError {}
+
+/// An error returned by the timed borrows, like [`SynCell::borrow_timeout`].
+#[cfg(all(feature = "std", not(loom), not(unchecked)))]
+#[derive(Debug)]
+pub struct TimeoutError {
+    _private: (),
+}
+
+#[cfg(all(feature = "std", not(loom), not(unchecked)))]
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SynCell borrow has timed out!")
+    }
+}
+
+#[cfg(all(feature = "std", not(loom), not(unchecked)))]
+impl Error for TimeoutError {}
+
+/// An error returned by the poison-checking borrows, such as [`SynCell::borrow_checked`].
+/// Indicates that a mutable borrow was released during a panic,
+/// so the value may be in an inconsistent state.
+///
+/// The error carries the guard, so the value can still be accessed.
+#[cfg(feature = "poison")]
+pub struct PoisonError<G> {
+    guard: G,
+}
+
+#[cfg(feature = "poison")]
+impl<G> PoisonError<G> {
+    /// Consume the error, returning the guard.
+    pub fn into_inner(self) -> G {
+        self.guard
+    }
+
+    /// Get a reference to the guard.
+    pub fn get_ref(&self) -> &G {
+        &self.guard
+    }
+
+    /// Get a mutable reference to the guard.
+    pub fn get_mut(&mut self) -> &mut G {
+        &mut self.guard
+    }
+}
+
+#[cfg(feature = "poison")]
+impl<G> fmt::Debug for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PoisonError").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "poison")]
+impl<G> fmt::Display for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SynCell is poisoned!")
+    }
+}
+
+#[cfg(feature = "poison")]
+impl<G> Error for PoisonError<G> {}
+
+/// A result of the poison-checking borrows.
+#[cfg(feature = "poison")]
+pub type LockResult<G> = Result<G, PoisonError<G>>;
 
 /// A shared reference to `SynCell` data.
-pub struct SynRef<'a, T> {
-    state: &'a AtomicUsize,
-    value: &'a T,
+pub struct SynRef<'a, T: ?Sized> {
+    state: StateRef<'a>,
+    value: NonNull<T>,
+    _marker: PhantomData<&'a T>,
 }
 
-impl<T> Drop for SynRef<'_, T> {
+unsafe impl<T: ?Sized + Sync> Send for SynRef<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for SynRef<'_, T> {}
+
+impl<T: ?Sized> Drop for SynRef<'_, T> {
     fn drop(&mut self) {
-        self.state.fetch_sub(1, Ordering::Release);
+        self.state.release_shared();
     }
 }
 
-impl<T> ops::Deref for SynRef<'_, T> {
+impl<T: ?Sized> ops::Deref for SynRef<'_, T> {
     type Target = T;
     fn deref(&self) -> &T {
-        self.value
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for SynRef<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for SynRef<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<'a, T: ?Sized> SynRef<'a, T> {
+    /// Get the version of the borrowed value, see [`SynCell::version`].
+    /// It doesn't change while the value is borrowed.
+    ///
+    /// This is an associated function, use as `SynRef::version(&r)`.
+    #[cfg(feature = "version")]
+    pub fn version(orig: &Self) -> usize {
+        orig.state.version()
+    }
+
+    /// Make another shared reference to the same data.
+    ///
+    /// Panics if there are too many shared borrows.
+    ///
+    /// This is an associated function, use as `SynRef::clone(&r)`.
+    #[allow(clippy::should_implement_trait)]
+    pub fn clone(orig: &Self) -> Self {
+        orig.state.clone_shared();
+        SynRef {
+            state: orig.state,
+            value: orig.value,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Make a new reference to a component of the borrowed data.
+    ///
+    /// This is an associated function, use as `SynRef::map(r, ...)`.
+    pub fn map<U: ?Sized, F>(orig: Self, f: F) -> SynRef<'a, U>
+    where
+        F: FnOnce(&T) -> &U,
+    {
+        let value = NonNull::from(f(unsafe { orig.value.as_ref() }));
+        let state = orig.state;
+        mem::forget(orig);
+        SynRef {
+            state,
+            value,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Make a new reference to an optional component of the borrowed data.
+    /// The original guard is returned back if the closure returns `None`.
+    ///
+    /// This is an associated function, use as `SynRef::filter_map(r, ...)`.
+    pub fn filter_map<U: ?Sized, F>(orig: Self, f: F) -> Result<SynRef<'a, U>, Self>
+    where
+        F: FnOnce(&T) -> Option<&U>,
+    {
+        match f(unsafe { orig.value.as_ref() }) {
+            Some(value) => {
+                let value = NonNull::from(value);
+                let state = orig.state;
+                mem::forget(orig);
+                Ok(SynRef {
+                    state,
+                    value,
+                    _marker: PhantomData,
+                })
+            }
+            None => Err(orig),
+        }
     }
 }
 
 /// A mutable reference to `SynCell` data.
-pub struct SynRefMut<'a, T> {
-    state: &'a AtomicUsize,
-    value: &'a mut T,
+pub struct SynRefMut<'a, T: ?Sized> {
+    state: StateRef<'a>,
+    value: NonNull<T>,
+    panicking: Panicking,
+    _marker: PhantomData<&'a mut T>,
 }
 
-impl<T> Drop for SynRefMut<'_, T> {
+unsafe impl<T: ?Sized + Send> Send for SynRefMut<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for SynRefMut<'_, T> {}
+
+impl<T: ?Sized> Drop for SynRefMut<'_, T> {
     fn drop(&mut self) {
-        self.state.fetch_and(!WRITE_BIT, Ordering::Release);
+        self.state.release_exclusive(self.panicking);
     }
 }
 
-impl<T> ops::Deref for SynRefMut<'_, T> {
+impl<T: ?Sized> ops::Deref for SynRefMut<'_, T> {
     type Target = T;
     fn deref(&self) -> &T {
-        self.value
+        unsafe { self.value.as_ref() }
     }
 }
 
-impl<T> ops::DerefMut for SynRefMut<'_, T> {
+impl<T: ?Sized + fmt::Debug> fmt::Debug for SynRefMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for SynRefMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized> ops::DerefMut for SynRefMut<'_, T> {
     fn deref_mut(&mut self) -> &mut T {
-        self.value
+        unsafe { self.value.as_mut() }
+    }
+}
+
+impl<'a, T: ?Sized> SynRefMut<'a, T> {
+    /// Turn into a shared reference, atomically.
+    /// No other writer can get in between.
+    ///
+    /// Panics if the borrow has been split into multiple guards.
+    ///
+    /// This is an associated function, use as `SynRefMut::downgrade(r)`.
+    pub fn downgrade(orig: Self) -> SynRef<'a, T> {
+        if !orig.state.try_downgrade() {
+            // Release before panicking, so that the cell is not poisoned.
+            drop(orig);
+            panic!("Can't downgrade a split mutable borrow!");
+        }
+        let state = orig.state;
+        let value = orig.value;
+        mem::forget(orig);
+        SynRef {
+            state,
+            value,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Make a new mutable reference to a component of the borrowed data.
+    ///
+    /// This is an associated function, use as `SynRefMut::map(r, ...)`.
+    pub fn map<U: ?Sized, F>(mut orig: Self, f: F) -> SynRefMut<'a, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        let value = NonNull::from(f(unsafe { orig.value.as_mut() }));
+        let (state, panicking) = (orig.state, orig.panicking);
+        mem::forget(orig);
+        SynRefMut {
+            state,
+            value,
+            panicking,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Split into two mutable references to different components of the borrowed data.
+    /// The cell stays borrowed until both of them are dropped.
+    ///
+    /// This is an associated function, use as `SynRefMut::map_split(r, ...)`.
+    pub fn map_split<U: ?Sized, V: ?Sized, F>(
+        mut orig: Self,
+        f: F,
+    ) -> (SynRefMut<'a, U>, SynRefMut<'a, V>)
+    where
+        F: FnOnce(&mut T) -> (&mut U, &mut V),
+    {
+        let (a, b) = f(unsafe { orig.value.as_mut() });
+        let (a, b) = (NonNull::from(a), NonNull::from(b));
+        let (state, panicking) = (orig.state, orig.panicking);
+        mem::forget(orig);
+        state.split_exclusive();
+        (
+            SynRefMut {
+                state,
+                value: a,
+                panicking,
+                _marker: PhantomData,
+            },
+            SynRefMut {
+                state,
+                value: b,
+                panicking,
+                _marker: PhantomData,
+            },
+        )
+    }
+
+    /// Make a new mutable reference to an optional component of the borrowed data.
+    /// The original guard is returned back if the closure returns `None`.
+    ///
+    /// This is an associated function, use as `SynRefMut::filter_map(r, ...)`.
+    pub fn filter_map<U: ?Sized, F>(mut orig: Self, f: F) -> Result<SynRefMut<'a, U>, Self>
+    where
+        F: FnOnce(&mut T) -> Option<&mut U>,
+    {
+        match f(unsafe { orig.value.as_mut() }) {
+            Some(value) => {
+                let value = NonNull::from(value);
+                let (state, panicking) = (orig.state, orig.panicking);
+                mem::forget(orig);
+                Ok(SynRefMut {
+                    state,
+                    value,
+                    panicking,
+                    _marker: PhantomData,
+                })
+            }
+            None => Err(orig),
+        }
+    }
+}
+
+impl<'a, T> SynRefMut<'a, [T]> {
+    /// Split a slice borrow into two at an index.
+    /// The cell stays borrowed until both of them are dropped.
+    ///
+    /// Panics if `mid > len`.
+    ///
+    /// This is an associated function, use as `SynRefMut::split_at(r, mid)`.
+    pub fn split_at(orig: Self, mid: usize) -> (Self, Self) {
+        Self::map_split(orig, |slice| slice.split_at_mut(mid))
+    }
+
+    /// Split a slice borrow into chunks of `chunk_size` elements,
+    /// the last chunk may be shorter.
+    /// The cell stays borrowed until all of them are dropped.
+    ///
+    /// Panics if `chunk_size` is 0.
+    ///
+    /// This is an associated function, use as `SynRefMut::chunks_mut(r, size)`.
+    pub fn chunks_mut(orig: Self, chunk_size: usize) -> ChunksMut<'a, T> {
+        assert_ne!(chunk_size, 0, "Chunk size must be non-zero");
+        ChunksMut {
+            rest: Some(orig),
+            chunk_size,
+        }
+    }
+}
+
+/// An iterator over the mutable borrows of slice chunks,
+/// returned by [`SynRefMut::chunks_mut`].
+pub struct ChunksMut<'a, T> {
+    rest: Option<SynRefMut<'a, [T]>>,
+    chunk_size: usize,
+}
+
+impl<'a, T> Iterator for ChunksMut<'a, T> {
+    type Item = SynRefMut<'a, [T]>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.rest.take()?;
+        if rest.len() <= self.chunk_size {
+            return if rest.is_empty() { None } else { Some(rest) };
+        }
+        let (chunk, rest) = SynRefMut::split_at(rest, self.chunk_size);
+        self.rest = Some(rest);
+        Some(chunk)
+    }
+}
+
+/// A shared reference to `SynCell` data, which can be upgraded to a mutable one.
+/// There can only be one upgradable reference at a time, but it can co-exist
+/// with regular shared references.
+pub struct SynRefUpgradable<'a, T: ?Sized> {
+    state: StateRef<'a>,
+    value: NonNull<T>,
+    _marker: PhantomData<&'a T>,
+}
+
+// Upgrading on the receiving thread gives mutable access there.
+unsafe impl<T: ?Sized + Send + Sync> Send for SynRefUpgradable<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for SynRefUpgradable<'_, T> {}
+
+impl<T: ?Sized> Drop for SynRefUpgradable<'_, T> {
+    fn drop(&mut self) {
+        self.state.release_upgradable();
+    }
+}
+
+impl<T: ?Sized> ops::Deref for SynRefUpgradable<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for SynRefUpgradable<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for SynRefUpgradable<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<'a, T: ?Sized> SynRefUpgradable<'a, T> {
+    /// Turn into a mutable reference.
+    ///
+    /// Panics if there are other shared references.
+    ///
+    /// This is an associated function, use as `SynRefUpgradable::upgrade(r)`.
+    #[track_caller]
+    pub fn upgrade(orig: Self) -> SynRefMut<'a, T> {
+        if !orig.state.try_upgrade() {
+            panic!("{}", orig.state.borrow_mut_error(BorrowKind::Shared));
+        }
+        let state = orig.state;
+        let value = orig.value;
+        mem::forget(orig);
+        SynRefMut {
+            state,
+            value,
+            panicking: Panicking::now(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Turn into a mutable reference, or return back the original
+    /// if there are other shared references.
+    ///
+    /// This is an associated function, use as `SynRefUpgradable::try_upgrade(r)`.
+    #[cfg(not(unchecked))]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn try_upgrade(orig: Self) -> Result<SynRefMut<'a, T>, Self> {
+        if !orig.state.try_upgrade() {
+            return Err(orig);
+        }
+        let state = orig.state;
+        let value = orig.value;
+        mem::forget(orig);
+        Ok(SynRefMut {
+            state,
+            value,
+            panicking: Panicking::now(),
+            _marker: PhantomData,
+        })
+    }
+
+    /// Turn into a regular shared reference, atomically.
+    ///
+    /// This is an associated function, use as `SynRefUpgradable::downgrade(r)`.
+    pub fn downgrade(orig: Self) -> SynRef<'a, T> {
+        orig.state.downgrade_upgradable();
+        let state = orig.state;
+        let value = orig.value;
+        mem::forget(orig);
+        SynRef {
+            state,
+            value,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Releases a borrow on drop, even if the code using it panics.
+struct Release<'a> {
+    state: &'a State,
+    release: fn(&State),
+}
+
+impl Drop for Release<'_> {
+    fn drop(&mut self) {
+        (self.release)(self.state);
+    }
+}
+
+/// Releases a mutable borrow on drop, poisoning the cell if the code using it panics.
+struct ReleaseMut<'a> {
+    state: &'a State,
+    panicking: Panicking,
+}
+
+impl Drop for ReleaseMut<'_> {
+    fn drop(&mut self) {
+        self.state.release_exclusive(self.panicking);
     }
 }
 
 /// A Sync cell. Stores a value of type `T` and allows
 /// to access it behind a reference. `SynCell` follows Rust borrowing
 /// rules but checks them at run time as opposed to compile time.
-pub struct SynCell<T> {
-    state: AtomicUsize,
+pub struct SynCell<T: ?Sized> {
+    state: State,
     value: UnsafeCell<T>,
 }
 
-unsafe impl<T> Sync for SynCell<T> {}
+unsafe impl<T: ?Sized> Sync for SynCell<T> {}
+
+#[cfg(feature = "nightly")]
+impl<T: ops::CoerceUnsized<U>, U> ops::CoerceUnsized<SynCell<U>> for SynCell<T> {}
+#[cfg(feature = "nightly")]
+impl<'b, T: ?Sized + core::marker::Unsize<U>, U: ?Sized> ops::CoerceUnsized<SynRef<'b, U>>
+    for SynRef<'b, T>
+{
+}
+#[cfg(feature = "nightly")]
+impl<'b, T: ?Sized + core::marker::Unsize<U>, U: ?Sized> ops::CoerceUnsized<SynRefMut<'b, U>>
+    for SynRefMut<'b, T>
+{
+}
 
 impl<T: Default> Default for SynCell<T> {
     fn default() -> Self {
@@ -77,58 +700,546 @@ impl<T: Default> Default for SynCell<T> {
     }
 }
 
+impl<T> From<T> for SynCell<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for SynCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut d = f.debug_struct("SynCell");
+        // Without the checks, there is no way to tell if the value can be read.
+        #[cfg(unchecked)]
+        d.field("value", &format_args!("<unchecked>"));
+        #[cfg(not(unchecked))]
+        match self.try_borrow() {
+            Ok(r) => d.field("value", &&*r),
+            Err(_) => d.field("value", &format_args!("<mutably borrowed>")),
+        };
+        d.finish()
+    }
+}
+
+/// Panics if the value is currently mutably borrowed.
+impl<T: Clone> Clone for SynCell<T> {
+    #[track_caller]
+    fn clone(&self) -> Self {
+        Self::new(self.borrow().clone())
+    }
+}
+
+/// Panics if either value is currently mutably borrowed.
+impl<T: ?Sized + PartialEq> PartialEq for SynCell<T> {
+    #[track_caller]
+    fn eq(&self, other: &Self) -> bool {
+        *self.borrow() == *other.borrow()
+    }
+}
+
+impl<T: ?Sized + Eq> Eq for SynCell<T> {}
+
+/// Panics if either value is currently mutably borrowed.
+impl<T: ?Sized + PartialOrd> PartialOrd for SynCell<T> {
+    #[track_caller]
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        self.borrow().partial_cmp(&*other.borrow())
+    }
+}
+
+/// Panics if either value is currently mutably borrowed.
+impl<T: ?Sized + Ord> Ord for SynCell<T> {
+    #[track_caller]
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.borrow().cmp(&*other.borrow())
+    }
+}
+
+/// Panics if the value is currently mutably borrowed.
+impl<T: ?Sized + hash::Hash> hash::Hash for SynCell<T> {
+    #[track_caller]
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.borrow().hash(state);
+    }
+}
+
 impl<T> SynCell<T> {
     /// Create a new cell.
+    ///
+    /// This is a `const fn`, so it can be used to initialize statics.
+    #[cfg(not(loom))]
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: State::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Create a new cell.
+    #[cfg(loom)]
     pub fn new(value: T) -> Self {
         Self {
-            state: AtomicUsize::new(0),
+            state: State::new(),
             value: UnsafeCell::new(value),
         }
     }
 
+    /// Create a new cell with an uninitialized value.
+    pub fn new_uninit() -> SynCell<mem::MaybeUninit<T>> {
+        SynCell::new(mem::MaybeUninit::uninit())
+    }
+
     /// Convert into the value.
     pub fn into_inner(self) -> T {
-        debug_assert_eq!(self.state.load(Ordering::Acquire), 0);
+        debug_assert!(self.state.is_unused());
         self.value.into_inner()
     }
 
+    /// Replace the value, returning the old one.
+    ///
+    /// Panics if the value is already borrowed in any way.
+    #[track_caller]
+    pub fn replace(&self, value: T) -> T {
+        mem::replace(&mut *self.borrow_mut(), value)
+    }
+
+    /// Replace the value with the one computed from the old one,
+    /// returning the old value.
+    ///
+    /// Panics if the value is already borrowed in any way.
+    #[track_caller]
+    pub fn replace_with<F: FnOnce(&mut T) -> T>(&self, f: F) -> T {
+        let mut guard = self.borrow_mut();
+        let value = f(&mut guard);
+        mem::replace(&mut *guard, value)
+    }
+
+    /// Swap the values of two cells.
+    /// Swapping a cell with itself does nothing.
+    ///
+    /// The cells are borrowed in the address order, and if any of them
+    /// fails, the other one is released before panicking.
+    #[track_caller]
+    pub fn swap(&self, other: &Self) {
+        if ptr::eq(self, other) {
+            return;
+        }
+        let (mut a, mut b) = borrow_mut_pair(self, other);
+        mem::swap(&mut *a, &mut *b);
+    }
+
+    /// Set the value, dropping the old one.
+    ///
+    /// Panics if the value is already borrowed in any way.
+    #[track_caller]
+    pub fn set(&self, value: T) {
+        drop(self.replace(value));
+    }
+
+    /// Take the value, leaving `Default::default()` in its place.
+    ///
+    /// Panics if the value is already borrowed in any way.
+    #[track_caller]
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        self.replace(T::default())
+    }
+
+    /// Modify a copy of the value, and put it back if the closure returns.
+    /// If the closure panics, the value is left intact, and the cell is not poisoned.
+    ///
+    /// Panics if the value is already borrowed in any way.
+    #[track_caller]
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R
+    where
+        T: Clone,
+    {
+        match self.try_update(|value| Ok::<R, core::convert::Infallible>(f(value))) {
+            Ok(result) => result,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Modify a copy of the value, and put it back if the closure returns `Ok`.
+    /// If the closure returns `Err` or panics, the value is left intact,
+    /// and the cell is not poisoned.
+    ///
+    /// Panics if the value is already borrowed in any way.
+    #[track_caller]
+    pub fn try_update<R, E>(&self, f: impl FnOnce(&mut T) -> Result<R, E>) -> Result<R, E>
+    where
+        T: Clone,
+    {
+        if let Err(e) = self.state.try_borrow_mut() {
+            panic!("{}", e);
+        }
+        let _release = Release {
+            state: &self.state,
+            release: State::release_exclusive_intact,
+        };
+        let value = unsafe { &mut *self.value.get() };
+        let mut copy = value.clone();
+        let result = f(&mut copy);
+        if result.is_ok() {
+            *value = copy;
+            self.state.modified();
+        }
+        result
+    }
+}
+
+impl<T> SynCell<mem::MaybeUninit<T>> {
+    /// Initialize the value, returning a mutable reference to it.
+    /// The old value is not dropped, if there was one.
+    ///
+    /// Panics if the value is already borrowed in any way.
+    #[track_caller]
+    pub fn write(&self, value: T) -> SynRefMut<'_, T> {
+        SynRefMut::map(self.borrow_mut(), |uninit| uninit.write(value))
+    }
+
+    /// Convert into a cell with an initialized value.
+    ///
+    /// # Safety
+    /// The value must have been initialized, e.g. with [`SynCell::write`].
+    pub unsafe fn assume_init(self) -> SynCell<T> {
+        SynCell::new(self.into_inner().assume_init())
+    }
+}
+
+impl<T: ?Sized> SynCell<T> {
     /// Get a direct mutable reference to the data.
     pub fn get_mut(&mut self) -> &mut T {
-        debug_assert_eq!(self.state.load(Ordering::Acquire), 0);
+        debug_assert!(self.state.is_unused());
         self.value.get_mut()
     }
 
+    /// Inspect the current state of the borrows.
+    ///
+    /// The result may be outdated by the time it's returned,
+    /// so this is mostly useful for diagnostics, like finding leaked guards.
+    #[cfg(not(unchecked))]
+    pub fn borrow_state(&self) -> BorrowState {
+        self.state.borrow_state()
+    }
+
+    /// Get the number of times the value could have been modified,
+    /// i.e. how many mutable borrows were released.
+    /// The result may be outdated by the time it's returned.
+    #[cfg(feature = "version")]
+    pub fn version(&self) -> usize {
+        self.state.version()
+    }
+
+    /// Get the borrow counters of this cell.
+    /// They are not counted without the borrow state, see the crate docs.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> Metrics {
+        self.state.metrics()
+    }
+
+    /// Forget all the current borrows, e.g. to recover from leaked guards.
+    ///
+    /// # Safety
+    /// There must be no live guards of this cell.
+    pub unsafe fn force_reset(&self) {
+        self.state.force_reset();
+    }
+
     /// Borrow immutably (can be shared).
     ///
     /// Panics if the value is already borrowed mutably.
-    pub fn borrow(&self) -> SynRef<T> {
-        let old = self.state.fetch_add(1, Ordering::AcqRel);
-        if old & WRITE_BIT != 0 {
-            self.state.fetch_sub(1, Ordering::Release);
-            panic!("SynCell is mutably borrowed elsewhere!");
+    #[track_caller]
+    pub fn borrow(&self) -> SynRef<'_, T> {
+        if let Err(e) = self.state.try_borrow() {
+            panic!("{}", e);
         }
         SynRef {
-            state: &self.state,
-            value: unsafe { &*self.value.get() },
+            state: StateRef::new(&self.state),
+            value: unsafe { NonNull::new_unchecked(self.value.get()) },
+            _marker: PhantomData,
         }
     }
 
+    /// Borrow immutably (can be shared), returning an error
+    /// if the value is already borrowed mutably.
+    #[cfg(not(unchecked))]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn try_borrow(&self) -> Result<SynRef<'_, T>, BorrowError> {
+        self.state.try_borrow()?;
+        Ok(SynRef {
+            state: StateRef::new(&self.state),
+            value: unsafe { NonNull::new_unchecked(self.value.get()) },
+            _marker: PhantomData,
+        })
+    }
+
     /// Borrow mutably (exclusive).
     ///
     /// Panics if the value is already borrowed in any way.
-    pub fn borrow_mut(&self) -> SynRefMut<T> {
-        let old = self.state.fetch_or(WRITE_BIT, Ordering::AcqRel);
-        if old & WRITE_BIT != 0 {
-            panic!("SynCell is mutably borrowed elsewhere!");
-        } else if old != 0 {
-            self.state.fetch_and(!WRITE_BIT, Ordering::Release);
-            panic!("SynCell is immutably borrowed elsewhere!");
+    #[track_caller]
+    pub fn borrow_mut(&self) -> SynRefMut<'_, T> {
+        if let Err(e) = self.state.try_borrow_mut() {
+            panic!("{}", e);
         }
         SynRefMut {
+            state: StateRef::new(&self.state),
+            value: unsafe { NonNull::new_unchecked(self.value.get()) },
+            panicking: Panicking::now(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Borrow mutably (exclusive), returning an error
+    /// if the value is already borrowed in any way.
+    #[cfg(not(unchecked))]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn try_borrow_mut(&self) -> Result<SynRefMut<'_, T>, BorrowMutError> {
+        self.state.try_borrow_mut()?;
+        Ok(SynRefMut {
+            state: StateRef::new(&self.state),
+            value: unsafe { NonNull::new_unchecked(self.value.get()) },
+            panicking: Panicking::now(),
+            _marker: PhantomData,
+        })
+    }
+
+    /// Borrow mutably (exclusive), allowing the current thread
+    /// to nest the mutable borrows.
+    ///
+    /// If the current thread already borrows the value mutably, this adds
+    /// another mutable guard, going one level deeper. The value is released
+    /// once the guards of all levels are dropped.
+    ///
+    /// Panics if the value is borrowed by another thread, or immutably.
+    ///
+    /// # Safety
+    ///
+    /// The outer mutable guards must not be used while the returned one is alive.
+    #[cfg(all(feature = "reentrancy", not(loom)))]
+    #[track_caller]
+    pub unsafe fn borrow_mut_reentrant(&self) -> SynRefMut<'_, T> {
+        if let Err(e) = self.state.try_borrow_mut_reentrant() {
+            panic!("{}", e);
+        }
+        SynRefMut {
+            state: StateRef::new(&self.state),
+            value: unsafe { NonNull::new_unchecked(self.value.get()) },
+            panicking: Panicking::now(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Borrow immutably for the duration of the closure.
+    ///
+    /// Panics if the value is already borrowed mutably.
+    #[track_caller]
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        if let Err(e) = self.state.try_borrow() {
+            panic!("{}", e);
+        }
+        let _release = Release {
+            state: &self.state,
+            release: State::release_shared,
+        };
+        f(unsafe { &*self.value.get() })
+    }
+
+    /// Borrow mutably for the duration of the closure.
+    ///
+    /// Panics if the value is already borrowed in any way.
+    #[track_caller]
+    pub fn with_mut<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        if let Err(e) = self.state.try_borrow_mut() {
+            panic!("{}", e);
+        }
+        let _release = ReleaseMut {
             state: &self.state,
-            value: unsafe { &mut *self.value.get() },
+            panicking: Panicking::now(),
+        };
+        f(unsafe { &mut *self.value.get() })
+    }
+
+    /// Check if a mutable borrow was released during a panic.
+    #[cfg(feature = "poison")]
+    pub fn is_poisoned(&self) -> bool {
+        self.state.is_poisoned()
+    }
+
+    /// Clear the poisoned state, e.g. after the value was fixed up.
+    #[cfg(feature = "poison")]
+    pub fn clear_poison(&self) {
+        self.state.clear_poison();
+    }
+
+    /// Borrow immutably (can be shared), checking for poison.
+    ///
+    /// Panics if the value is already borrowed mutably.
+    #[cfg(feature = "poison")]
+    #[track_caller]
+    pub fn borrow_checked(&self) -> LockResult<SynRef<'_, T>> {
+        let guard = self.borrow();
+        if self.is_poisoned() {
+            Err(PoisonError { guard })
+        } else {
+            Ok(guard)
+        }
+    }
+
+    /// Borrow mutably (exclusive), checking for poison.
+    ///
+    /// Panics if the value is already borrowed in any way.
+    #[cfg(feature = "poison")]
+    #[track_caller]
+    pub fn borrow_mut_checked(&self) -> LockResult<SynRefMut<'_, T>> {
+        let guard = self.borrow_mut();
+        if self.is_poisoned() {
+            Err(PoisonError { guard })
+        } else {
+            Ok(guard)
+        }
+    }
+
+    /// Borrow immutably with an intent to upgrade to a mutable borrow later.
+    /// Can be shared with regular immutable borrows.
+    ///
+    /// Panics if the value is already borrowed mutably or upgradably.
+    #[track_caller]
+    pub fn borrow_upgradable(&self) -> SynRefUpgradable<'_, T> {
+        if let Err(e) = self.state.try_borrow_upgradable() {
+            panic!("{}", e);
+        }
+        SynRefUpgradable {
+            state: StateRef::new(&self.state),
+            value: unsafe { NonNull::new_unchecked(self.value.get()) },
+            _marker: PhantomData,
+        }
+    }
+
+    /// Borrow immutably with an intent to upgrade to a mutable borrow later,
+    /// returning an error if the value is already borrowed mutably or upgradably.
+    #[cfg(not(unchecked))]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn try_borrow_upgradable(&self) -> Result<SynRefUpgradable<'_, T>, BorrowMutError> {
+        self.state.try_borrow_upgradable()?;
+        Ok(SynRefUpgradable {
+            state: StateRef::new(&self.state),
+            value: unsafe { NonNull::new_unchecked(self.value.get()) },
+            _marker: PhantomData,
+        })
+    }
+
+    /// Borrow immutably (can be shared), waiting for a mutable borrow
+    /// to be released if there is one.
+    ///
+    /// The thread spins for a bit, and then parks until woken up by the release.
+    #[cfg(all(feature = "std", not(loom), not(unchecked)))]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn borrow_blocking(&self) -> SynRef<'_, T> {
+        let mut backoff = park::Backoff::new();
+        loop {
+            if let Ok(r) = self.try_borrow() {
+                return r;
+            }
+            if !backoff.spin() {
+                self.state.park(BorrowKind::Shared, None);
+            }
+        }
+    }
+
+    /// Borrow immutably (can be shared), waiting for a mutable borrow
+    /// to be released until the deadline.
+    #[cfg(all(feature = "std", not(loom), not(unchecked)))]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn borrow_until(&self, deadline: Instant) -> Result<SynRef<'_, T>, TimeoutError> {
+        let mut backoff = park::Backoff::new();
+        loop {
+            if let Ok(r) = self.try_borrow() {
+                return Ok(r);
+            }
+            if Instant::now() >= deadline {
+                return Err(TimeoutError { _private: () });
+            }
+            if !backoff.spin() {
+                self.state.park(BorrowKind::Shared, Some(deadline));
+            }
+        }
+    }
+
+    /// Borrow immutably (can be shared), waiting for a mutable borrow
+    /// to be released for up to the given time.
+    #[cfg(all(feature = "std", not(loom), not(unchecked)))]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn borrow_timeout(&self, timeout: Duration) -> Result<SynRef<'_, T>, TimeoutError> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.borrow_until(deadline),
+            None => Ok(self.borrow_blocking()),
+        }
+    }
+
+    /// Borrow mutably (exclusive), waiting for any other borrows
+    /// to be released.
+    ///
+    /// The thread spins for a bit, and then parks until woken up by the release.
+    #[cfg(all(feature = "std", not(loom), not(unchecked)))]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn borrow_mut_blocking(&self) -> SynRefMut<'_, T> {
+        let mut backoff = park::Backoff::new();
+        loop {
+            if let Ok(r) = self.try_borrow_mut() {
+                return r;
+            }
+            if !backoff.spin() {
+                self.state.park(BorrowKind::Exclusive, None);
+            }
         }
     }
+
+    /// Borrow mutably (exclusive), waiting for any other borrows
+    /// to be released until the deadline.
+    #[cfg(all(feature = "std", not(loom), not(unchecked)))]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn borrow_mut_until(&self, deadline: Instant) -> Result<SynRefMut<'_, T>, TimeoutError> {
+        let mut backoff = park::Backoff::new();
+        loop {
+            if let Ok(r) = self.try_borrow_mut() {
+                return Ok(r);
+            }
+            if Instant::now() >= deadline {
+                return Err(TimeoutError { _private: () });
+            }
+            if !backoff.spin() {
+                self.state.park(BorrowKind::Exclusive, Some(deadline));
+            }
+        }
+    }
+
+    /// Borrow mutably (exclusive), waiting for any other borrows
+    /// to be released for up to the given time.
+    #[cfg(all(feature = "std", not(loom), not(unchecked)))]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn borrow_mut_timeout(&self, timeout: Duration) -> Result<SynRefMut<'_, T>, TimeoutError> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.borrow_mut_until(deadline),
+            None => Ok(self.borrow_mut_blocking()),
+        }
+    }
+
+    /// Borrow immutably (can be shared), asynchronously waiting for a mutable borrow
+    /// to be released if there is one.
+    #[cfg(all(feature = "async", not(loom), not(unchecked)))]
+    pub fn borrow_async(&self) -> BorrowFuture<'_, T> {
+        BorrowFuture { cell: self }
+    }
+
+    /// Borrow mutably (exclusive), asynchronously waiting for any other borrows
+    /// to be released.
+    #[cfg(all(feature = "async", not(loom), not(unchecked)))]
+    pub fn borrow_mut_async(&self) -> BorrowMutFuture<'_, T> {
+        BorrowMutFuture { cell: self }
+    }
 }
 
 #[test]
@@ -145,6 +1256,7 @@ fn valid() {
     }
 }
 
+#[cfg(not(unchecked))]
 #[test]
 #[should_panic]
 fn bad_write_write() {
@@ -153,6 +1265,7 @@ fn bad_write_write() {
     let _b2 = sc.borrow_mut();
 }
 
+#[cfg(not(unchecked))]
 #[test]
 #[should_panic]
 fn bad_read_write() {
@@ -161,6 +1274,7 @@ fn bad_read_write() {
     let _b2 = sc.borrow_mut();
 }
 
+#[cfg(not(unchecked))]
 #[test]
 #[should_panic]
 fn bad_write_read() {
@@ -169,10 +1283,547 @@ fn bad_write_read() {
     let _b2 = sc.borrow();
 }
 
+#[test]
+#[cfg(not(loom))]
+fn statics() {
+    static GLOBAL: SynCell<u8> = SynCell::new(1);
+    static TABLE: [SynCell<u8>; 2] = [SynCell::new(2), SynCell::new(3)];
+    *GLOBAL.borrow_mut() += 1;
+    assert_eq!(*GLOBAL.borrow() + *TABLE[1].borrow(), 5);
+    assert_eq!(SynCell::from(4u8).into_inner(), 4);
+}
+
+#[test]
+fn uninit() {
+    let sc = SynCell::<String>::new_uninit();
+    sc.write("hello".to_string()).push('!');
+    let sc = unsafe { sc.assume_init() };
+    assert_eq!(*sc.borrow(), "hello!");
+}
+
+#[cfg(not(unchecked))]
+#[test]
+#[should_panic]
+fn bad_uninit() {
+    let sc = SynCell::<u8>::new_uninit();
+    let _r = sc.borrow();
+    sc.write(0);
+}
+
+#[cfg(not(unchecked))]
+#[test]
+fn traits() {
+    use std::hash::{BuildHasher, RandomState};
+    let a = SynCell::new(1u8);
+    let b = a.clone();
+    assert_eq!(a, b);
+    *b.borrow_mut() = 2;
+    assert!(a < b);
+    assert_eq!(a.cmp(&b), cmp::Ordering::Less);
+    let hasher = RandomState::new();
+    assert_eq!(hasher.hash_one(&a), hasher.hash_one(1u8));
+
+    let c = SynCell::new(3u8);
+    assert_eq!(format!("{:?}", c), "SynCell { value: 3 }");
+    let w = c.borrow_mut();
+    assert_eq!(format!("{:?}", c), "SynCell { value: <mutably borrowed> }");
+    assert_eq!(format!("{:?} {}", w, w), "3 3");
+}
+
+#[cfg(not(unchecked))]
+#[test]
+fn try_borrow() {
+    let sc = SynCell::new(0u8);
+    {
+        let _b1 = sc.borrow();
+        assert!(sc.try_borrow().is_ok());
+        assert!(sc.try_borrow_mut().is_err());
+    }
+    {
+        let _b1 = sc.borrow_mut();
+        assert!(sc.try_borrow().is_err());
+        assert!(sc.try_borrow_mut().is_err());
+    }
+    // failed attempts must not leave the state dirty
+    assert!(sc.try_borrow_mut().is_ok());
+    assert_eq!(sc.into_inner(), 0);
+}
+
+#[cfg(not(unchecked))]
+#[test]
+fn clone_ref() {
+    let sc = SynCell::new(1u8);
+    let r1 = sc.borrow();
+    let r2 = SynRef::clone(&r1);
+    drop(r1);
+    assert!(sc.try_borrow_mut().is_err());
+    assert_eq!(*r2, 1);
+    drop(r2);
+    assert!(sc.try_borrow_mut().is_ok());
+}
+
+#[cfg(not(unchecked))]
+#[test]
+fn leaks() {
+    let sc = SynCell::new(0u8);
+    assert_eq!(sc.borrow_state(), BorrowState::Unused);
+    let r = sc.borrow();
+    let u = sc.borrow_upgradable();
+    assert_eq!(
+        sc.borrow_state(),
+        BorrowState::Reading {
+            readers: 1,
+            upgradable: true
+        }
+    );
+    drop(u);
+    mem::forget(r);
+    assert_eq!(
+        sc.borrow_state(),
+        BorrowState::Reading {
+            readers: 1,
+            upgradable: false
+        }
+    );
+    unsafe { sc.force_reset() };
+    mem::forget(sc.borrow_mut());
+    assert_eq!(sc.borrow_state(), BorrowState::Writing);
+    unsafe { sc.force_reset() };
+    assert_eq!(sc.borrow_state(), BorrowState::Unused);
+}
+
+#[test]
+#[should_panic]
+#[cfg(debug_assertions)]
+fn leak_on_drop() {
+    let sc = SynCell::new(0u8);
+    mem::forget(sc.borrow());
+}
+
+#[test]
+fn with() {
+    let sc = SynCell::new(1u8);
+    assert_eq!(sc.with_mut(|v| mem::replace(v, 2)), 1);
+    assert_eq!(sc.with(|v| sc.with(|w| *v + *w)), 4);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        sc.with_mut(|_| panic!("oops"))
+    }));
+    assert!(result.is_err());
+    #[cfg(not(unchecked))]
+    assert_eq!(sc.borrow_state(), BorrowState::Unused);
+}
+
+#[test]
+fn update() {
+    let sc = SynCell::new(vec![1u8]);
+    assert_eq!(sc.update(|v| v.pop()), Some(1));
+    let result = sc.try_update(|v| {
+        v.push(2);
+        Err::<(), _>("nope")
+    });
+    assert_eq!(result, Err("nope"));
+    let result = sc.try_update(|v| {
+        v.push(3);
+        Ok::<_, ()>(v.len())
+    });
+    assert_eq!(result, Ok(1));
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        sc.update(|v| {
+            v.clear();
+            panic!("oops")
+        })
+    }));
+    assert!(result.is_err());
+    #[cfg(feature = "poison")]
+    assert!(!sc.is_poisoned());
+    assert_eq!(*sc.borrow(), [3]);
+}
+
+#[cfg(not(unchecked))]
+#[test]
+#[should_panic]
+fn bad_with() {
+    let sc = SynCell::new(1u8);
+    sc.with(|_| sc.with_mut(|_| ()));
+}
+
+#[test]
+#[cfg(all(feature = "metrics", not(unchecked)))]
+fn metrics() {
+    let sc = SynCell::new(0u8);
+    let r1 = sc.borrow();
+    let r2 = SynRef::clone(&r1);
+    assert!(sc.try_borrow_mut().is_err());
+    drop((r1, r2));
+    *sc.borrow_mut() += 1;
+    assert_eq!(
+        sc.metrics(),
+        Metrics {
+            borrows: 3,
+            conflicts: 1,
+            max_readers: 2,
+        }
+    );
+}
+
+#[test]
+#[cfg(feature = "version")]
+fn version() {
+    let sc = SynCell::new(0u8);
+    assert_eq!(sc.version(), 0);
+    *sc.borrow_mut() = 1;
+    let r = sc.borrow();
+    assert_eq!(SynRef::version(&r), 1);
+    drop(r);
+    let r = SynRefMut::downgrade(sc.borrow_mut());
+    assert_eq!(SynRef::version(&r), 2);
+    drop(r);
+    assert_eq!(sc.try_update(|_| Err::<(), _>(())), Err(()));
+    assert_eq!(sc.version(), 2);
+    sc.update(|v| *v = 3);
+    assert_eq!(sc.version(), 3);
+}
+
+#[cfg(not(unchecked))]
+#[test]
+fn map() {
+    let sc = SynCell::new((0u8, [1u8, 2]));
+    {
+        let mut bw = SynRefMut::map(sc.borrow_mut(), |v| &mut v.1);
+        bw[0] += 10;
+        assert!(sc.try_borrow().is_err());
+    }
+    {
+        let b1 = SynRef::map(sc.borrow(), |v| &v.1[0]);
+        let b2 = SynRef::filter_map(sc.borrow(), |v| v.1.get(1))
+            .ok()
+            .unwrap();
+        assert_eq!(*b1 + *b2, 13);
+        let b3 = SynRef::filter_map(sc.borrow(), |v| v.1.get(2))
+            .err()
+            .unwrap();
+        assert_eq!(b3.0, 0);
+        assert!(sc.try_borrow_mut().is_err());
+    }
+    assert!(SynRefMut::filter_map(sc.borrow_mut(), |v| v.1.get_mut(3)).is_err());
+    assert_eq!(sc.into_inner(), (0, [11, 2]));
+}
+
+#[cfg(not(unchecked))]
+#[test]
+fn downgrade_upgrade() {
+    let sc = SynCell::new(0u8);
+    let mut bw = sc.borrow_mut();
+    *bw = 1;
+    let b1 = SynRefMut::downgrade(bw);
+    let b2 = sc.borrow();
+    assert!(sc.try_borrow_mut().is_err());
+    assert_eq!(*b1 + *b2, 2);
+    let bu = sc.borrow_upgradable();
+    assert!(sc.try_borrow_upgradable().is_err());
+    drop((b1, b2));
+    let _b3 = sc.borrow();
+    let bu = SynRefUpgradable::try_upgrade(bu).err().unwrap();
+    drop(_b3);
+    let mut bw = SynRefUpgradable::upgrade(bu);
+    *bw = 2;
+    assert!(sc.try_borrow_upgradable().is_err());
+    drop(bw);
+    let bu = SynRefUpgradable::downgrade(sc.borrow_upgradable());
+    assert!(sc.try_borrow_upgradable().is_ok());
+    assert_eq!(*bu, 2);
+    drop(bu);
+    assert_eq!(sc.into_inner(), 2);
+}
+
+#[cfg(not(unchecked))]
+#[cfg(feature = "poison")]
+#[test]
+fn poison() {
+    use std::panic;
+    let sc = SynCell::new(0u8);
+    assert!(sc.borrow_mut_checked().is_ok());
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        let mut bw = sc.borrow_mut();
+        *bw = 1;
+        panic!("oops");
+    }));
+    assert!(result.is_err());
+    assert!(sc.is_poisoned());
+    {
+        let err = sc.borrow_checked().err().unwrap();
+        assert_eq!(**err.get_ref(), 1);
+        assert!(sc.try_borrow_mut().is_err());
+    }
+    *sc.borrow_mut_checked().err().unwrap().into_inner() = 0;
+    sc.clear_poison();
+    assert!(sc.borrow_checked().is_ok());
+}
+
+#[cfg(feature = "poison")]
+#[test]
+fn poison_during_unwind() {
+    use std::panic;
+    struct Bump<'a>(&'a SynCell<u8>);
+    impl Drop for Bump<'_> {
+        fn drop(&mut self) {
+            *self.0.borrow_mut() += 1;
+            self.0.with_mut(|v| *v += 1);
+        }
+    }
+    let sc = SynCell::new(0u8);
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        let _bump = Bump(&sc);
+        panic!("unrelated");
+    }));
+    assert!(result.is_err());
+    // The borrows were taken during the unwind, they don't poison.
+    assert!(!sc.is_poisoned());
+    assert_eq!(*sc.borrow(), 2);
+}
+
+#[cfg(not(unchecked))]
+#[cfg(feature = "debug-tracking")]
+#[test]
+fn debug_tracking() {
+    let sc = SynCell::new(0u8);
+    let line = line!() + 1;
+    let bw = sc.borrow_mut();
+    let message = sc.try_borrow().err().unwrap().to_string();
+    assert!(
+        message.contains(&format!("{}:{}", file!(), line)),
+        "{}",
+        message
+    );
+    assert!(message.contains("by thread"), "{}", message);
+    // The released and downgraded borrows are not blamed.
+    let _b = SynRefMut::downgrade(bw);
+    let message = sc.try_borrow_mut().err().unwrap().to_string();
+    assert_eq!(message, "SynCell is immutably borrowed elsewhere!");
+    drop(_b);
+    let bu = sc.borrow_upgradable();
+    let message = sc.try_borrow_upgradable().err().unwrap().to_string();
+    assert!(message.contains("(at "), "{}", message);
+    drop(bu);
+    let _b = sc.borrow();
+    let message = sc.try_borrow_mut().err().unwrap().to_string();
+    assert_eq!(message, "SynCell is immutably borrowed elsewhere!");
+}
+
+#[test]
+fn replace_swap() {
+    let sc = SynCell::new(1u8);
+    assert_eq!(sc.replace(2), 1);
+    assert_eq!(sc.replace_with(|v| *v + 1), 2);
+    sc.set(4);
+    let other = SynCell::new(5u8);
+    sc.swap(&other);
+    other.swap(&other);
+    assert_eq!(sc.take(), 5);
+    assert_eq!((sc.into_inner(), other.into_inner()), (0, 4));
+}
+
+#[cfg(not(unchecked))]
+#[test]
+#[should_panic]
+fn bad_swap() {
+    let a = SynCell::new(0u8);
+    let b = SynCell::new(1u8);
+    let _b1 = b.borrow();
+    a.swap(&b);
+}
+
+#[cfg(not(unchecked))]
+#[test]
+fn split() {
+    let sc = SynCell::new([0u8; 5]);
+    let (mut a, mut b) = SynRefMut::split_at(SynRefMut::map(sc.borrow_mut(), |v| &mut v[..]), 2);
+    a[1] = 1;
+    b[0] = 2;
+    drop(a);
+    assert!(sc.try_borrow().is_err());
+    let chunks = SynRefMut::chunks_mut(b, 2).collect::<Vec<_>>();
+    assert_eq!(chunks.len(), 2);
+    for mut chunk in chunks {
+        let last = chunk.len() - 1;
+        chunk[last] = 3;
+        assert!(sc.try_borrow().is_err());
+    }
+    assert_eq!(*sc.borrow(), [0, 1, 2, 3, 3]);
+    let (_a, _b) = SynRefMut::map_split(sc.borrow_mut(), |v| {
+        let (a, b) = v.split_at_mut(1);
+        (&mut a[0], b)
+    });
+    assert!(sc.try_borrow_mut().is_err());
+}
+
+#[cfg(not(unchecked))]
+#[test]
+#[should_panic]
+fn bad_split_downgrade() {
+    let sc = SynCell::new([0u8; 2]);
+    let (a, _b) = SynRefMut::split_at(SynRefMut::map(sc.borrow_mut(), |v| &mut v[..]), 1);
+    SynRefMut::downgrade(a);
+}
+
+#[cfg(not(unchecked))]
+#[test]
+fn split_downgrade_intact() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    let sc = SynCell::new([0u8; 2]);
+    let (a, b) = SynRefMut::split_at(SynRefMut::map(sc.borrow_mut(), |v| &mut v[..]), 1);
+    assert!(catch_unwind(AssertUnwindSafe(|| SynRefMut::downgrade(a))).is_err());
+    assert_eq!(sc.borrow_state(), BorrowState::Writing);
+    drop(b);
+    #[cfg(feature = "poison")]
+    assert!(!sc.is_poisoned());
+    // One bump per released guard.
+    #[cfg(feature = "version")]
+    assert_eq!(sc.version(), 2);
+    assert!(sc.try_borrow_mut().is_ok());
+}
+
+#[cfg(not(unchecked))]
+#[test]
+fn unsized_values() {
+    let sc: &SynCell<[u8]> = &SynCell::new([1u8, 2, 3, 4]);
+    sc.borrow_mut()[1] = 0;
+    assert_eq!(sc.borrow().iter().sum::<u8>(), 8);
+    let sd: &SynCell<dyn fmt::Display> = &SynCell::new(5u8);
+    assert_eq!(sd.borrow().to_string(), "5");
+    let _b = SynRef::map(sc.borrow(), |v| &v[1..]);
+    assert!(sc.try_borrow_mut().is_err());
+}
+
+#[cfg(feature = "nightly")]
+#[test]
+fn coerce_unsized() {
+    let sc = SynCell::new([1u8, 2, 3, 4]);
+    let b: SynRef<[u8]> = sc.borrow();
+    assert_eq!(b.len(), 4);
+    drop(b);
+    let mut bw: SynRefMut<[u8]> = sc.borrow_mut();
+    bw[0] = 0;
+    drop(bw);
+    let sr: SynCell<&[u8]> = SynCell::new(&[1u8, 2]);
+    assert_eq!(sr.borrow().len(), 2);
+}
+
+#[cfg(not(unchecked))]
+#[cfg(all(feature = "std", not(loom)))]
+#[test]
+fn blocking() {
+    use std::{sync::Arc, thread, time::Duration};
+    let sc = Arc::new(SynCell::new(0u8));
+    let bw = sc.borrow_mut();
+    let jh = thread::spawn({
+        let sc = Arc::clone(&sc);
+        move || *sc.borrow_blocking()
+    });
+    thread::sleep(Duration::from_millis(10));
+    *{ bw } = 1;
+    assert_eq!(jh.join().unwrap(), 1);
+}
+
+#[cfg(not(unchecked))]
+#[cfg(all(feature = "std", not(loom)))]
+#[test]
+fn timeout() {
+    use std::{sync::Arc, thread};
+    let sc = Arc::new(SynCell::new(0u8));
+    let br = sc.borrow();
+    assert!(sc.borrow_mut_timeout(Duration::from_millis(1)).is_err());
+    assert_eq!(*sc.borrow_timeout(Duration::from_millis(1)).unwrap(), 0);
+    let jh = thread::spawn({
+        let sc = Arc::clone(&sc);
+        move || {
+            *sc.borrow_mut_until(Instant::now() + Duration::from_secs(10))
+                .unwrap() = 1;
+        }
+    });
+    thread::sleep(Duration::from_millis(10));
+    drop(br);
+    jh.join().unwrap();
+    let bw = sc.borrow_mut();
+    assert!(sc.borrow_until(Instant::now()).is_err());
+    assert_eq!(*bw, 1);
+}
+
+#[cfg(not(unchecked))]
+#[cfg(all(feature = "reentrancy", not(loom)))]
+#[test]
+fn reentrancy() {
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        thread,
+    };
+    let sc = SynCell::new(0u8);
+    let mut outer = sc.borrow_mut();
+    let e = sc.try_borrow().unwrap_err().to_string();
+    assert!(e.contains("reentrant borrow on the same thread"), "{}", e);
+    thread::scope(|s| {
+        s.spawn(|| {
+            let e = sc.try_borrow_mut().unwrap_err().to_string();
+            assert!(e.contains("borrowed by another thread"), "{}", e);
+            let reentrant = catch_unwind(AssertUnwindSafe(|| unsafe { sc.borrow_mut_reentrant() }));
+            assert!(reentrant.is_err());
+        });
+    });
+    *outer = 1;
+    {
+        let mut inner = unsafe { sc.borrow_mut_reentrant() };
+        *inner += 1;
+        let mut innermost = unsafe { sc.borrow_mut_reentrant() };
+        drop(inner);
+        *innermost += 1;
+    }
+    assert_eq!(sc.borrow_state(), BorrowState::Writing);
+    drop(outer);
+    // Not nested, so works like a regular mutable borrow.
+    let r = SynRefMut::downgrade(unsafe { sc.borrow_mut_reentrant() });
+    let e = sc.try_borrow_mut().unwrap_err().to_string();
+    assert!(
+        !e.contains("same thread") && !e.contains("another thread"),
+        "{}",
+        e
+    );
+    assert_eq!(*r, 3);
+}
+
+#[cfg(all(feature = "std", not(loom), not(unchecked)))]
+#[test]
+fn fight_blocking() {
+    use std::{sync::Arc, thread};
+    const NUM_THREADS: usize = 4;
+    const NUM_LOCKS: usize = if cfg!(miri) { 50 } else { 1000 };
+    let value = Arc::new(SynCell::new(0usize));
+    let join_handles = (0..NUM_THREADS)
+        .map(|i| {
+            let value = Arc::clone(&value);
+            thread::spawn(move || {
+                for j in 0..NUM_LOCKS {
+                    if (i + j) % 2 == 0 {
+                        *value.borrow_mut_blocking() += 1;
+                    } else {
+                        let _ = *value.borrow_blocking();
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for jh in join_handles {
+        jh.join().unwrap();
+    }
+    assert_eq!(*value.borrow(), NUM_THREADS * NUM_LOCKS / 2);
+}
+
 #[test]
 fn fight() {
-    use mystd::{
-        sync::{Arc, RwLock},
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, RwLock,
+        },
         thread,
     };
     const NUM_THREADS: usize = 3;
@@ -0,0 +1,118 @@
+//! Integration with `lock_api` with "lock_api" feature.
+//!
+//! `RawSynLock` is the bare borrow state, so `lock_api::RwLock<RawSynLock, T>`
+//! behaves like `SynCell<T>`: locking panics on conflict instead of waiting.
+
+use super::{state::State, BorrowKind, BorrowState};
+
+/// Raw borrow state of `SynCell`, implementing `lock_api` traits.
+pub struct RawSynLock {
+    state: State,
+}
+
+unsafe impl lock_api::RawRwLock for RawSynLock {
+    const INIT: Self = Self {
+        state: State::new(),
+    };
+
+    type GuardMarker = lock_api::GuardSend;
+
+    fn lock_shared(&self) {
+        if let Err(e) = self.state.try_borrow() {
+            panic!("{}", e);
+        }
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        self.state.try_borrow().is_ok()
+    }
+
+    unsafe fn unlock_shared(&self) {
+        self.state.release_shared();
+    }
+
+    fn lock_exclusive(&self) {
+        if let Err(e) = self.state.try_borrow_mut() {
+            panic!("{}", e);
+        }
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        self.state.try_borrow_mut().is_ok()
+    }
+
+    unsafe fn unlock_exclusive(&self) {
+        // Lock guards don't remember if the thread was panicking,
+        // and `lock_api` has no poisoning anyway.
+        self.state.release_exclusive_intact();
+    }
+
+    fn is_locked(&self) -> bool {
+        !self.state.is_unused()
+    }
+
+    fn is_locked_exclusive(&self) -> bool {
+        self.state.borrow_state() == BorrowState::Writing
+    }
+}
+
+unsafe impl lock_api::RawRwLockDowngrade for RawSynLock {
+    unsafe fn downgrade(&self) {
+        // Lock guards can't be split.
+        let downgraded = self.state.try_downgrade();
+        debug_assert!(downgraded);
+    }
+}
+
+unsafe impl lock_api::RawRwLockUpgrade for RawSynLock {
+    fn lock_upgradable(&self) {
+        if let Err(e) = self.state.try_borrow_upgradable() {
+            panic!("{}", e);
+        }
+    }
+
+    fn try_lock_upgradable(&self) -> bool {
+        self.state.try_borrow_upgradable().is_ok()
+    }
+
+    unsafe fn unlock_upgradable(&self) {
+        self.state.release_upgradable();
+    }
+
+    unsafe fn upgrade(&self) {
+        if !self.state.try_upgrade() {
+            panic!("{}", self.state.borrow_mut_error(BorrowKind::Shared));
+        }
+    }
+
+    unsafe fn try_upgrade(&self) -> bool {
+        self.state.try_upgrade()
+    }
+}
+
+#[test]
+fn rw_lock() {
+    type RwLock<T> = lock_api::RwLock<RawSynLock, T>;
+    let lock = RwLock::new(0u8);
+    {
+        let r1 = lock.read();
+        let r2 = lock.read();
+        assert!(lock.try_write().is_none());
+        assert_eq!(*r1 + *r2, 0);
+    }
+    *lock.write() = 1;
+    let u = lock.upgradable_read();
+    assert!(lock.try_upgradable_read().is_none());
+    let w = lock_api::RwLockUpgradableReadGuard::upgrade(u);
+    assert!(lock.is_locked_exclusive());
+    let r = lock_api::RwLockWriteGuard::downgrade(w);
+    assert_eq!(*r, 1);
+}
+
+#[test]
+#[should_panic]
+fn rw_lock_conflict() {
+    let lock = lock_api::RwLock::<RawSynLock, _>::new(0u8);
+    let _w = lock.write();
+    let _r = lock.read();
+}
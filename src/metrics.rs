@@ -0,0 +1,54 @@
+//! Contention metrics with "metrics" feature.
+//!
+//! Each state counts its borrows and conflicts with relaxed atomics.
+//! This is cheap, but not free, since the counters are shared between threads.
+//! Like the tracking sites, the counters are not modeled by Loom.
+
+#[cfg(not(feature = "portable-atomic"))]
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "portable-atomic")]
+use portable_atomic::{AtomicUsize, Ordering};
+
+/// Snapshot of the borrow counters of a cell.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Number of successful borrows of any kind.
+    pub borrows: usize,
+    /// Number of failed borrow attempts, including the retries of the blocking borrows.
+    pub conflicts: usize,
+    /// Highest number of simultaneous shared borrows.
+    pub max_readers: usize,
+}
+
+pub(crate) struct Counters {
+    borrows: AtomicUsize,
+    conflicts: AtomicUsize,
+    max_readers: AtomicUsize,
+}
+
+impl Counters {
+    pub const fn new() -> Self {
+        Self {
+            borrows: AtomicUsize::new(0),
+            conflicts: AtomicUsize::new(0),
+            max_readers: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn borrowed(&self, readers: usize) {
+        self.borrows.fetch_add(1, Ordering::Relaxed);
+        self.max_readers.fetch_max(readers, Ordering::Relaxed);
+    }
+
+    pub fn conflicted(&self) {
+        self.conflicts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Metrics {
+        Metrics {
+            borrows: self.borrows.load(Ordering::Relaxed),
+            conflicts: self.conflicts.load(Ordering::Relaxed),
+            max_readers: self.max_readers.load(Ordering::Relaxed),
+        }
+    }
+}
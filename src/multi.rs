@@ -0,0 +1,115 @@
+//! Borrowing multiple cells at once.
+//!
+//! The cells are borrowed in the address order, and if any of them fails,
+//! the ones already borrowed are released, so there is never a half-borrowed state.
+//! Without the borrow state, only the panicking variants are left, which just
+//! borrow the cells one by one.
+
+#[cfg(not(unchecked))]
+use super::BorrowMutError;
+use super::{SynCell, SynRefMut};
+#[cfg(not(unchecked))]
+use core::array;
+
+#[cfg(not(unchecked))]
+fn address<T: ?Sized>(cell: &SynCell<T>) -> usize {
+    cell as *const SynCell<T> as *const u8 as usize
+}
+
+/// Borrow two cells mutably, returning an error if any of them
+/// is already borrowed in any way, or if they are the same cell.
+#[cfg(not(unchecked))]
+#[cfg_attr(feature = "debug-tracking", track_caller)]
+pub fn try_borrow_mut_pair<'a, A: ?Sized, B: ?Sized>(
+    a: &'a SynCell<A>,
+    b: &'a SynCell<B>,
+) -> Result<(SynRefMut<'a, A>, SynRefMut<'a, B>), BorrowMutError> {
+    if address(a) <= address(b) {
+        let ra = a.try_borrow_mut()?;
+        let rb = b.try_borrow_mut()?;
+        Ok((ra, rb))
+    } else {
+        let rb = b.try_borrow_mut()?;
+        let ra = a.try_borrow_mut()?;
+        Ok((ra, rb))
+    }
+}
+
+/// Borrow two cells mutably.
+///
+/// Panics if any of them is already borrowed in any way, or if they are the same cell.
+#[track_caller]
+pub fn borrow_mut_pair<'a, A: ?Sized, B: ?Sized>(
+    a: &'a SynCell<A>,
+    b: &'a SynCell<B>,
+) -> (SynRefMut<'a, A>, SynRefMut<'a, B>) {
+    #[cfg(not(unchecked))]
+    match try_borrow_mut_pair(a, b) {
+        Ok(pair) => pair,
+        Err(e) => panic!("{}", e),
+    }
+    #[cfg(unchecked)]
+    (a.borrow_mut(), b.borrow_mut())
+}
+
+/// Borrow an array of cells mutably, returning an error if any of them
+/// is already borrowed in any way, or if any cell is repeated.
+#[cfg(not(unchecked))]
+#[cfg_attr(feature = "debug-tracking", track_caller)]
+pub fn try_borrow_mut_many<'a, T: ?Sized, const N: usize>(
+    cells: [&'a SynCell<T>; N],
+) -> Result<[SynRefMut<'a, T>; N], BorrowMutError> {
+    let mut order: [usize; N] = array::from_fn(|i| i);
+    order.sort_unstable_by_key(|&i| address(cells[i]));
+    let mut guards: [Option<SynRefMut<'a, T>>; N] = array::from_fn(|_| None);
+    for i in order {
+        guards[i] = Some(cells[i].try_borrow_mut()?);
+    }
+    Ok(guards.map(Option::unwrap))
+}
+
+/// Borrow an array of cells mutably.
+///
+/// Panics if any of them is already borrowed in any way, or if any cell is repeated.
+#[track_caller]
+pub fn borrow_mut_many<'a, T: ?Sized, const N: usize>(
+    cells: [&'a SynCell<T>; N],
+) -> [SynRefMut<'a, T>; N] {
+    #[cfg(not(unchecked))]
+    match try_borrow_mut_many(cells) {
+        Ok(guards) => guards,
+        Err(e) => panic!("{}", e),
+    }
+    #[cfg(unchecked)]
+    cells.map(SynCell::borrow_mut)
+}
+
+#[cfg(not(unchecked))]
+#[test]
+fn pair() {
+    let a = SynCell::new(1u8);
+    let b = SynCell::new([2u8]);
+    let (mut ra, rb) = borrow_mut_pair(&a, &b);
+    *ra += rb[0];
+    assert!(try_borrow_mut_pair(&b, &a).is_err());
+    drop((ra, rb));
+    assert!(try_borrow_mut_pair(&a, &a).is_err());
+    assert!(a.try_borrow_mut().is_ok());
+    assert_eq!(*a.borrow(), 3);
+}
+
+#[cfg(not(unchecked))]
+#[test]
+fn many() {
+    let cells = [SynCell::new(0u8), SynCell::new(1), SynCell::new(2)];
+    let [mut c0, c1, c2] = borrow_mut_many([&cells[2], &cells[0], &cells[1]]);
+    *c0 += *c1 + *c2;
+    drop((c0, c1, c2));
+    assert_eq!(*cells[2].borrow(), 3);
+
+    let _r = cells[1].borrow();
+    assert!(try_borrow_mut_many([&cells[0], &cells[1], &cells[2]]).is_err());
+    // Nothing is left borrowed after the failure.
+    assert!(cells[0].try_borrow_mut().is_ok());
+    assert!(cells[2].try_borrow_mut().is_ok());
+}
@@ -0,0 +1,357 @@
+//! One-time initialization.
+//!
+//! `SynOnceCell` uses a single state word, similar to `SynCell`.
+//! With "std" feature, the word remembers the initializing thread,
+//! and the other threads wait for it, parking like the blocking borrows.
+//! Reentrant initialization is a conflict, and we panic.
+//! Without "std", there is no way to tell the threads apart,
+//! so initializing the cell elsewhere is a conflict as well.
+
+use super::{
+    atomic::{AtomicUsize, Ordering},
+    state::PARKED_BIT,
+};
+use core::{cell::UnsafeCell, fmt, mem, ops};
+
+const EMPTY: usize = 0;
+const READY: usize = 1;
+/// Without "std", anybody initializing the cell.
+#[cfg_attr(all(feature = "std", not(loom)), allow(dead_code))]
+const INITIALIZING: usize = 2;
+
+/// State of the cell being initialized by the current thread.
+fn initializing() -> usize {
+    // Thread identifiers start from 1.
+    #[cfg(all(feature = "std", not(loom)))]
+    return READY + super::state::current_thread();
+    #[cfg(not(all(feature = "std", not(loom))))]
+    INITIALIZING
+}
+
+/// Move the state out of initialization, waking up anybody waiting.
+fn settle(state: &AtomicUsize, new: usize) {
+    let old = state.swap(new, Ordering::Release);
+    #[cfg(all(feature = "std", not(loom)))]
+    if old & PARKED_BIT != 0 {
+        super::park::unpark_all(state);
+    }
+    #[cfg(not(all(feature = "std", not(loom))))]
+    let _ = old;
+}
+
+/// Resets the state back to empty if the initializer panics.
+struct Reset<'a>(&'a AtomicUsize);
+
+impl Drop for Reset<'_> {
+    fn drop(&mut self) {
+        settle(self.0, EMPTY);
+    }
+}
+
+/// A Sync cell that can be written to only once.
+pub struct SynOnceCell<T> {
+    state: AtomicUsize,
+    value: UnsafeCell<mem::MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for SynOnceCell<T> {}
+unsafe impl<T: Send> Send for SynOnceCell<T> {}
+
+impl<T> Default for SynOnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SynOnceCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.get() {
+            Some(value) => f.debug_tuple("SynOnceCell").field(value).finish(),
+            None => f.write_str("SynOnceCell(<uninit>)"),
+        }
+    }
+}
+
+impl<T> Drop for SynOnceCell<T> {
+    fn drop(&mut self) {
+        if self.state.load(Ordering::Relaxed) & !PARKED_BIT == READY {
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+impl<T> SynOnceCell<T> {
+    /// Create a new empty cell.
+    #[cfg(not(loom))]
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(EMPTY),
+            value: UnsafeCell::new(mem::MaybeUninit::uninit()),
+        }
+    }
+
+    /// Loom atomics can't be created in const context.
+    #[cfg(loom)]
+    pub fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(EMPTY),
+            value: UnsafeCell::new(mem::MaybeUninit::uninit()),
+        }
+    }
+
+    /// Get the value, if it's initialized.
+    pub fn get(&self) -> Option<&T> {
+        // Waiters may leave the parked bit set even after initialization.
+        if self.state.load(Ordering::Acquire) & !PARKED_BIT == READY {
+            Some(unsafe { (*self.value.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    /// Get a mutable reference to the value, if it's initialized.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if self.state.load(Ordering::Relaxed) & !PARKED_BIT == READY {
+            Some(unsafe { self.value.get_mut().assume_init_mut() })
+        } else {
+            None
+        }
+    }
+
+    /// Try to start the initialization. Returns `Err` with the current state,
+    /// without the parked bit, if the cell is not empty.
+    fn start(&self) -> Result<Reset<'_>, usize> {
+        let mut cur = self.state.load(Ordering::Acquire);
+        loop {
+            if cur & !PARKED_BIT != EMPTY {
+                return Err(cur & !PARKED_BIT);
+            }
+            match self.state.compare_exchange_weak(
+                cur,
+                cur | initializing(),
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Ok(Reset(&self.state)),
+                Err(actual) => cur = actual,
+            }
+        }
+    }
+
+    /// Finish the initialization with a value.
+    fn finish(&self, reset: Reset<'_>, value: T) -> &T {
+        let value = unsafe { (*self.value.get()).write(value) };
+        mem::forget(reset);
+        settle(&self.state, READY);
+        value
+    }
+
+    /// Set the value, returning it back if the cell is already initialized,
+    /// or being initialized.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        match self.start() {
+            Ok(reset) => {
+                self.finish(reset, value);
+                Ok(())
+            }
+            Err(_) => Err(value),
+        }
+    }
+
+    /// Get the value, initializing it with `f` if the cell is empty.
+    ///
+    /// With "std" feature, waits if another thread is initializing the cell,
+    /// and panics on reentrant initialization from `f`.
+    /// Without it, panics if the cell is being initialized elsewhere.
+    /// If `f` panics, the cell stays empty.
+    #[cfg_attr(not(all(feature = "std", not(loom))), allow(clippy::never_loop))]
+    pub fn get_or_init<F: FnOnce() -> T>(&self, f: F) -> &T {
+        if let Some(value) = self.get() {
+            return value;
+        }
+        #[cfg(all(feature = "std", not(loom)))]
+        let mut backoff = super::park::Backoff::new();
+        loop {
+            match self.start() {
+                Ok(reset) => return self.finish(reset, f()),
+                Err(READY) => return unsafe { (*self.value.get()).assume_init_ref() },
+                #[cfg(all(feature = "std", not(loom)))]
+                Err(owner) if owner != initializing() => {
+                    if !backoff.spin() {
+                        super::park::park(&self.state, |s| s & !PARKED_BIT == owner, None);
+                    }
+                }
+                #[cfg(all(feature = "std", not(loom)))]
+                Err(_) => panic!("SynOnceCell is initialized reentrantly!"),
+                #[cfg(not(all(feature = "std", not(loom))))]
+                Err(_) => panic!("SynOnceCell is being initialized elsewhere!"),
+            }
+        }
+    }
+
+    /// Take the value out, leaving the cell empty.
+    pub fn take(&mut self) -> Option<T> {
+        if self.state.load(Ordering::Relaxed) & !PARKED_BIT == READY {
+            self.state.store(EMPTY, Ordering::Relaxed);
+            Some(unsafe { self.value.get_mut().assume_init_read() })
+        } else {
+            None
+        }
+    }
+
+    /// Convert into the value, if it's initialized.
+    pub fn into_inner(mut self) -> Option<T> {
+        self.take()
+    }
+}
+
+/// A value that is initialized on the first access.
+pub struct SynLazy<T, F = fn() -> T> {
+    cell: SynOnceCell<T>,
+    init: UnsafeCell<Option<F>>,
+}
+
+unsafe impl<T: Send + Sync, F: Send> Sync for SynLazy<T, F> {}
+
+impl<T: fmt::Debug, F> fmt::Debug for SynLazy<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.cell.get() {
+            Some(value) => f.debug_tuple("SynLazy").field(value).finish(),
+            None => f.write_str("SynLazy(<uninit>)"),
+        }
+    }
+}
+
+impl<T: Default> Default for SynLazy<T> {
+    fn default() -> Self {
+        Self::new(T::default)
+    }
+}
+
+impl<T, F: FnOnce() -> T> SynLazy<T, F> {
+    /// Create a new lazy value with the given initializer.
+    #[cfg(not(loom))]
+    pub const fn new(init: F) -> Self {
+        Self {
+            cell: SynOnceCell::new(),
+            init: UnsafeCell::new(Some(init)),
+        }
+    }
+
+    /// Loom atomics can't be created in const context.
+    #[cfg(loom)]
+    pub fn new(init: F) -> Self {
+        Self {
+            cell: SynOnceCell::new(),
+            init: UnsafeCell::new(Some(init)),
+        }
+    }
+
+    /// Force the initialization, returning the value.
+    ///
+    /// See [`SynOnceCell::get_or_init`] for the concurrent access.
+    /// Panics if the initializer panicked before.
+    ///
+    /// This is an associated function, use as `SynLazy::force(&lazy)`.
+    pub fn force(this: &Self) -> &T {
+        this.cell.get_or_init(|| {
+            // Only the thread that started the initialization gets here.
+            match unsafe { (*this.init.get()).take() } {
+                Some(init) => init(),
+                None => panic!("SynLazy initializer has panicked before!"),
+            }
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> ops::Deref for SynLazy<T, F> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        Self::force(self)
+    }
+}
+
+#[test]
+fn once() {
+    let oc = SynOnceCell::new();
+    assert!(oc.get().is_none());
+    assert_eq!(*oc.get_or_init(|| 1u8), 1);
+    assert_eq!(*oc.get_or_init(|| 2), 1);
+    assert_eq!(oc.set(3), Err(3));
+    assert_eq!(oc.into_inner(), Some(1));
+}
+
+#[test]
+#[cfg(not(loom))]
+fn lazy_static() {
+    static LAZY: SynLazy<Vec<u8>> = SynLazy::new(|| vec![1, 2]);
+    assert_eq!(LAZY.len(), 2);
+}
+
+#[test]
+#[cfg(all(feature = "std", not(loom)))]
+fn lazy_concurrent() {
+    use std::{
+        sync::{atomic::AtomicUsize, Barrier},
+        thread,
+        time::Duration,
+    };
+    let count = AtomicUsize::new(0);
+    let barrier = Barrier::new(4);
+    let lazy = SynLazy::new(|| {
+        count.fetch_add(1, Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(10));
+        5u8
+    });
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                barrier.wait();
+                assert_eq!(*lazy, 5);
+            });
+        }
+    });
+    assert_eq!(count.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn once_parked() {
+    let value = std::sync::Arc::new(());
+    let mut oc = SynOnceCell::new();
+    assert!(oc.set(std::sync::Arc::clone(&value)).is_ok());
+    // As if left by a waiter that has gone.
+    oc.state.fetch_or(PARKED_BIT, Ordering::Relaxed);
+    assert!(oc.get_mut().is_some());
+    drop(oc);
+    assert_eq!(std::sync::Arc::strong_count(&value), 1);
+}
+
+#[test]
+#[should_panic]
+fn once_reentrant() {
+    let oc = SynOnceCell::new();
+    oc.get_or_init(|| *oc.get_or_init(|| 1u8) + 1);
+}
+
+#[test]
+fn once_panic() {
+    let oc = SynOnceCell::new();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        oc.get_or_init(|| -> u8 { panic!("oops") });
+    }));
+    assert!(result.is_err());
+    assert!(oc.set(1).is_ok());
+    assert_eq!(oc.get(), Some(&1));
+}
+
+#[test]
+fn lazy() {
+    let count = std::cell::Cell::new(0);
+    let lazy = SynLazy::new(|| {
+        count.set(count.get() + 1);
+        vec![1u8, 2]
+    });
+    assert_eq!(lazy.len(), 2);
+    assert_eq!(lazy[1], 2);
+    assert_eq!(count.get(), 1);
+}
@@ -0,0 +1,174 @@
+//! Owned guards, keeping the cell alive with an `Arc`.
+//!
+//! These are useful when the borrow has to outlive the reference
+//! to the cell, e.g. when returning it from a function.
+
+#[cfg(not(unchecked))]
+use super::{BorrowError, BorrowMutError};
+use super::{Panicking, SynCell};
+use alloc::sync::Arc;
+use core::{fmt, ops};
+
+/// An owned shared reference to `SynCell` data.
+pub struct SynRefArc<T: ?Sized> {
+    cell: Arc<SynCell<T>>,
+}
+
+// Like with `Arc`, the cell can be reached from any thread holding the guard,
+// and dropped or borrowed mutably there.
+unsafe impl<T: ?Sized + Send + Sync> Send for SynRefArc<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for SynRefArc<T> {}
+
+impl<T: ?Sized> Drop for SynRefArc<T> {
+    fn drop(&mut self) {
+        self.cell.state.release_shared();
+    }
+}
+
+impl<T: ?Sized> ops::Deref for SynRefArc<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.cell.value.get() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for SynRefArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for SynRefArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized> SynRefArc<T> {
+    /// Get the cell that is borrowed.
+    ///
+    /// This is an associated function, use as `SynRefArc::cell(&r)`.
+    pub fn cell(orig: &Self) -> &Arc<SynCell<T>> {
+        &orig.cell
+    }
+}
+
+/// An owned mutable reference to `SynCell` data.
+pub struct SynRefMutArc<T: ?Sized> {
+    cell: Arc<SynCell<T>>,
+    panicking: Panicking,
+}
+
+unsafe impl<T: ?Sized + Send + Sync> Send for SynRefMutArc<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for SynRefMutArc<T> {}
+
+impl<T: ?Sized> Drop for SynRefMutArc<T> {
+    fn drop(&mut self) {
+        self.cell.state.release_exclusive(self.panicking);
+    }
+}
+
+impl<T: ?Sized> ops::Deref for SynRefMutArc<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.cell.value.get() }
+    }
+}
+
+impl<T: ?Sized> ops::DerefMut for SynRefMutArc<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.cell.value.get() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for SynRefMutArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for SynRefMutArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized> SynRefMutArc<T> {
+    /// Get the cell that is borrowed.
+    ///
+    /// This is an associated function, use as `SynRefMutArc::cell(&r)`.
+    pub fn cell(orig: &Self) -> &Arc<SynCell<T>> {
+        &orig.cell
+    }
+}
+
+impl<T: ?Sized> SynCell<T> {
+    /// Borrow immutably (can be shared), keeping the cell alive.
+    ///
+    /// Panics if the value is already borrowed mutably.
+    #[track_caller]
+    pub fn borrow_owned(self: &Arc<Self>) -> SynRefArc<T> {
+        if let Err(e) = self.state.try_borrow() {
+            panic!("{}", e);
+        }
+        SynRefArc {
+            cell: Arc::clone(self),
+        }
+    }
+
+    /// Borrow immutably (can be shared), keeping the cell alive,
+    /// and returning an error if the value is already borrowed mutably.
+    #[cfg(not(unchecked))]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn try_borrow_owned(self: &Arc<Self>) -> Result<SynRefArc<T>, BorrowError> {
+        self.state.try_borrow()?;
+        Ok(SynRefArc {
+            cell: Arc::clone(self),
+        })
+    }
+
+    /// Borrow mutably (exclusive), keeping the cell alive.
+    ///
+    /// Panics if the value is already borrowed in any way.
+    #[track_caller]
+    pub fn borrow_mut_owned(self: &Arc<Self>) -> SynRefMutArc<T> {
+        if let Err(e) = self.state.try_borrow_mut() {
+            panic!("{}", e);
+        }
+        SynRefMutArc {
+            cell: Arc::clone(self),
+            panicking: Panicking::now(),
+        }
+    }
+
+    /// Borrow mutably (exclusive), keeping the cell alive,
+    /// and returning an error if the value is already borrowed in any way.
+    #[cfg(not(unchecked))]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn try_borrow_mut_owned(self: &Arc<Self>) -> Result<SynRefMutArc<T>, BorrowMutError> {
+        self.state.try_borrow_mut()?;
+        Ok(SynRefMutArc {
+            cell: Arc::clone(self),
+            panicking: Panicking::now(),
+        })
+    }
+}
+
+#[cfg(not(unchecked))]
+#[test]
+fn owned() {
+    fn first(cell: Arc<SynCell<Vec<u8>>>) -> SynRefArc<Vec<u8>> {
+        cell.borrow_owned()
+    }
+
+    let cell = Arc::new(SynCell::new(vec![1u8]));
+    let mut w = cell.borrow_mut_owned();
+    w.push(2);
+    assert!(cell.try_borrow_owned().is_err());
+    drop(w);
+    let r = first(Arc::clone(&cell));
+    drop(cell);
+    assert!(SynRefArc::cell(&r).try_borrow_mut().is_err());
+    let r = std::thread::spawn(move || r.len()).join().unwrap();
+    assert_eq!(r, 2);
+}
@@ -0,0 +1,165 @@
+//! Waiting for a borrow to be released.
+//!
+//! Threads that fail to borrow first spin for a bit, and then register themselves
+//! in a global table keyed by the address of the cell state, and park.
+//! The state gets `PARKED_BIT` set, so that whoever releases a borrow
+//! knows that it has to wake the waiters up.
+//!
+//! With "async" feature, the same table also holds the wakers of the pending futures.
+
+use super::{
+    atomic::{AtomicUsize, Ordering},
+    state::PARKED_BIT,
+};
+#[cfg(feature = "async")]
+use std::task::Waker;
+use std::{
+    hint,
+    sync::{Mutex, MutexGuard, PoisonError},
+    thread::{self, Thread},
+    time::Instant,
+};
+
+const NUM_BUCKETS: usize = 64;
+
+enum Wake {
+    Thread(Thread),
+    #[cfg(feature = "async")]
+    Task(Waker),
+}
+
+impl Wake {
+    fn is_same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Thread(a), Self::Thread(b)) => a.id() == b.id(),
+            #[cfg(feature = "async")]
+            (Self::Task(a), Self::Task(b)) => a.will_wake(b),
+            #[cfg(feature = "async")]
+            _ => false,
+        }
+    }
+
+    fn wake(self) {
+        match self {
+            Self::Thread(thread) => thread.unpark(),
+            #[cfg(feature = "async")]
+            Self::Task(waker) => waker.wake(),
+        }
+    }
+}
+
+struct Waiter {
+    key: usize,
+    wake: Wake,
+}
+
+static BUCKETS: [Mutex<Vec<Waiter>>; NUM_BUCKETS] = [const { Mutex::new(Vec::new()) }; NUM_BUCKETS];
+
+fn lock_bucket(key: usize) -> MutexGuard<'static, Vec<Waiter>> {
+    BUCKETS[(key >> 3) % NUM_BUCKETS]
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Exponential backoff for spinning on a contended state.
+pub(crate) struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    const SPIN_LIMIT: u32 = 6;
+
+    pub fn new() -> Self {
+        Self { step: 0 }
+    }
+
+    /// Spin for a while. Returns `false` if it's time to park instead.
+    pub fn spin(&mut self) -> bool {
+        if self.step > Self::SPIN_LIMIT {
+            return false;
+        }
+        for _ in 0..1 << self.step {
+            hint::spin_loop();
+        }
+        self.step += 1;
+        true
+    }
+}
+
+/// Register a waiter to be woken up when the state is released.
+///
+/// `is_blocked` is checked against the state after `PARKED_BIT` is set,
+/// and if it returns `false`, nothing is registered, and `false` is returned.
+fn enqueue(state: &AtomicUsize, is_blocked: impl Fn(usize) -> bool, wake: Wake) -> bool {
+    let key = state as *const _ as usize;
+    let mut waiters = lock_bucket(key);
+    let old = state.fetch_or(PARKED_BIT, Ordering::AcqRel);
+    if !is_blocked(old) {
+        if !waiters.iter().any(|w| w.key == key) {
+            state.fetch_and(!PARKED_BIT, Ordering::Relaxed);
+        }
+        return false;
+    }
+    if !waiters
+        .iter()
+        .any(|w| w.key == key && w.wake.is_same(&wake))
+    {
+        waiters.push(Waiter { key, wake });
+    }
+    true
+}
+
+/// Park the current thread until the state is released, or the deadline is reached.
+///
+/// `is_blocked` is checked against the state after `PARKED_BIT` is set,
+/// and if it returns `false`, the thread doesn't park.
+///
+/// On timeout, the waiter stays registered until the next release,
+/// which is harmless: the thread would just get a spurious wake up.
+pub(crate) fn park(
+    state: &AtomicUsize,
+    is_blocked: impl Fn(usize) -> bool,
+    deadline: Option<Instant>,
+) {
+    if enqueue(state, is_blocked, Wake::Thread(thread::current())) {
+        match deadline {
+            Some(deadline) => {
+                thread::park_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => thread::park(),
+        }
+    }
+}
+
+/// Register the task waker to be woken up when the state is released.
+/// Returns `false` if the state is not blocked any more.
+#[cfg(feature = "async")]
+pub(crate) fn register(
+    state: &AtomicUsize,
+    is_blocked: impl Fn(usize) -> bool,
+    waker: &Waker,
+) -> bool {
+    enqueue(state, is_blocked, Wake::Task(waker.clone()))
+}
+
+/// Wake up all the threads parked on the state.
+#[cold]
+pub(crate) fn unpark_all(state: &AtomicUsize) {
+    let key = state as *const _ as usize;
+    let mut waiters = lock_bucket(key);
+    state.fetch_and(!PARKED_BIT, Ordering::Relaxed);
+    let mut woken = Vec::new();
+    let mut i = 0;
+    while i < waiters.len() {
+        if waiters[i].key == key {
+            woken.push(waiters.swap_remove(i).wake);
+        } else {
+            i += 1;
+        }
+    }
+    // Wake outside of the lock, since task wakers may run arbitrary code.
+    drop(waiters);
+    for wake in woken {
+        wake.wake();
+    }
+}
@@ -0,0 +1,229 @@
+//! Fair blocking borrows.
+//!
+//! `SynRwCell` wraps a `SynCell`, and changes the way the blocking borrows
+//! are granted, according to the fairness policy:
+//!   - preferring readers is what `SynCell` does, writers may starve
+//!   - preferring writers makes a waiting writer set a bit in the state,
+//!     which holds off the new blocking readers
+//!   - FIFO makes everybody take a ticket, and wait for their turn to borrow,
+//!     parking on the serving ticket like on a borrow state
+
+use super::{
+    atomic::{AtomicUsize, Ordering},
+    park::{self, Backoff},
+    state::PARKED_BIT,
+    BorrowKind, SynCell, SynRef, SynRefMut,
+};
+use std::ops;
+
+/// Policy of granting the blocking borrows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Fairness {
+    /// Let the readers in as long as there is no mutable borrow.
+    ReaderPreferring,
+    /// Hold off the new readers while a writer is waiting.
+    #[default]
+    WriterPreferring,
+    /// Grant the borrows in the order they were requested.
+    Fifo,
+}
+
+/// Lets the next ticket in on drop, even if the borrow panics.
+struct NextTicket<'a>(&'a AtomicUsize);
+
+impl Drop for NextTicket<'_> {
+    fn drop(&mut self) {
+        let old = self.0.fetch_add(1, Ordering::Release);
+        if old & PARKED_BIT != 0 {
+            park::unpark_all(self.0);
+        }
+    }
+}
+
+/// A `SynCell` with a fairness policy for the blocking borrows.
+///
+/// Other borrows are available through `Deref`, and don't follow the policy.
+pub struct SynRwCell<T: ?Sized> {
+    fairness: Fairness,
+    next_ticket: AtomicUsize,
+    serving_ticket: AtomicUsize,
+    cell: SynCell<T>,
+}
+
+impl<T: Default> Default for SynRwCell<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> SynRwCell<T> {
+    /// Create a new cell preferring writers.
+    pub fn new(value: T) -> Self {
+        Self::with_fairness(value, Fairness::default())
+    }
+
+    /// Create a new cell with the given fairness policy.
+    pub fn with_fairness(value: T, fairness: Fairness) -> Self {
+        Self {
+            fairness,
+            next_ticket: AtomicUsize::new(0),
+            serving_ticket: AtomicUsize::new(0),
+            cell: SynCell::new(value),
+        }
+    }
+
+    /// Convert into the value.
+    pub fn into_inner(self) -> T {
+        self.cell.into_inner()
+    }
+}
+
+impl<T: ?Sized> ops::Deref for SynRwCell<T> {
+    type Target = SynCell<T>;
+    fn deref(&self) -> &SynCell<T> {
+        &self.cell
+    }
+}
+
+impl<T: ?Sized> SynRwCell<T> {
+    /// Get the fairness policy.
+    pub fn fairness(&self) -> Fairness {
+        self.fairness
+    }
+
+    /// Run the blocking borrow in turn with the others.
+    fn in_turn<R>(&self, borrow: impl FnOnce() -> R) -> R {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        let is_waiting = |serving: usize| serving & !PARKED_BIT != ticket;
+        let mut backoff = Backoff::new();
+        while is_waiting(self.serving_ticket.load(Ordering::Acquire)) {
+            if !backoff.spin() {
+                park::park(&self.serving_ticket, is_waiting, None);
+            }
+        }
+        let _next = NextTicket(&self.serving_ticket);
+        borrow()
+    }
+
+    /// Borrow immutably (can be shared), waiting according to the fairness policy.
+    ///
+    /// Note that a thread already holding a shared borrow may deadlock
+    /// here if there is a writer waiting.
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn borrow_blocking(&self) -> SynRef<'_, T> {
+        match self.fairness {
+            Fairness::ReaderPreferring => self.cell.borrow_blocking(),
+            Fairness::WriterPreferring => {
+                let state = &self.cell.state;
+                let mut backoff = Backoff::new();
+                loop {
+                    if !state.is_writer_waiting() {
+                        if let Ok(r) = self.cell.try_borrow() {
+                            return r;
+                        }
+                    }
+                    if !backoff.spin() {
+                        state.park_behind_writers();
+                    }
+                }
+            }
+            Fairness::Fifo => self.in_turn(|| self.cell.borrow_blocking()),
+        }
+    }
+
+    /// Borrow mutably (exclusive), waiting according to the fairness policy.
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn borrow_mut_blocking(&self) -> SynRefMut<'_, T> {
+        match self.fairness {
+            Fairness::ReaderPreferring => self.cell.borrow_mut_blocking(),
+            Fairness::WriterPreferring => {
+                let state = &self.cell.state;
+                let mut backoff = Backoff::new();
+                loop {
+                    if let Ok(w) = self.cell.try_borrow_mut() {
+                        state.clear_writer_waiting();
+                        return w;
+                    }
+                    state.set_writer_waiting();
+                    if !backoff.spin() {
+                        state.park(BorrowKind::Exclusive, None);
+                    }
+                }
+            }
+            Fairness::Fifo => self.in_turn(|| self.cell.borrow_mut_blocking()),
+        }
+    }
+}
+
+#[cfg(test)]
+fn writer_first(fairness: Fairness, is_waiting: impl Fn(&SynRwCell<u8>) -> bool) {
+    use std::{sync::Arc, thread, time::Duration};
+
+    let cell = Arc::new(SynRwCell::with_fairness(0u8, fairness));
+    let r = cell.borrow();
+    let writer = thread::spawn({
+        let cell = Arc::clone(&cell);
+        move || *cell.borrow_mut_blocking() = 1
+    });
+    while !is_waiting(&cell) {
+        thread::sleep(Duration::from_millis(1));
+    }
+    let reader = thread::spawn({
+        let cell = Arc::clone(&cell);
+        move || *cell.borrow_blocking()
+    });
+    thread::sleep(Duration::from_millis(10));
+    assert!(!reader.is_finished());
+    drop(r);
+    writer.join().unwrap();
+    assert_eq!(reader.join().unwrap(), 1);
+}
+
+#[test]
+fn writer_preferring() {
+    writer_first(Fairness::WriterPreferring, |cell| {
+        cell.state.is_writer_waiting()
+    });
+}
+
+#[test]
+fn fifo() {
+    writer_first(Fairness::Fifo, |cell| {
+        cell.next_ticket.load(Ordering::Relaxed) == 1
+    });
+}
+
+#[test]
+fn fifo_panic() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let cell = SynRwCell::with_fairness(0u8, Fairness::Fifo);
+    let result = catch_unwind(AssertUnwindSafe(|| cell.in_turn(|| panic!("oops"))));
+    assert!(result.is_err());
+    assert_eq!(*cell.borrow_blocking(), 0);
+}
+
+#[test]
+fn fifo_parks() {
+    use std::{thread, time::Duration};
+
+    let cell = SynRwCell::with_fairness(0u8, Fairness::Fifo);
+    let r = cell.borrow();
+    thread::scope(|s| {
+        s.spawn(|| *cell.borrow_mut_blocking() = 1);
+        while cell.next_ticket.load(Ordering::Relaxed) != 1 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let reader = s.spawn(|| *cell.borrow_blocking());
+        // The reader behind the writer's ticket parks instead of spinning.
+        for _ in 0..1000 {
+            if cell.serving_ticket.load(Ordering::Relaxed) & PARKED_BIT != 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_ne!(cell.serving_ticket.load(Ordering::Relaxed) & PARKED_BIT, 0);
+        drop(r);
+        assert_eq!(reader.join().unwrap(), 1);
+    });
+}
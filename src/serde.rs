@@ -0,0 +1,58 @@
+//! Serialization support with "serde" feature.
+//!
+//! Like `RefCell`, the cell is serialized by borrowing the value,
+//! and it's an error if the value is mutably borrowed at the time.
+//! With `--cfg syncell_unchecked`, the release builds can't know that,
+//! so the value is borrowed with [`SynCell::borrow`] in all the builds,
+//! and the debug ones panic on the conflict instead.
+
+use super::SynCell;
+use ::serde::{de, ser};
+
+impl<T: ?Sized + ser::Serialize> ser::Serialize for SynCell<T> {
+    #[cfg(syncell_unchecked)]
+    #[track_caller]
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.borrow().serialize(serializer)
+    }
+
+    #[cfg(not(syncell_unchecked))]
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.try_borrow() {
+            Ok(value) => value.serialize(serializer),
+            Err(e) => Err(ser::Error::custom(e)),
+        }
+    }
+}
+
+impl<'de, T: de::Deserialize<'de>> de::Deserialize<'de> for SynCell<T> {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(SynCell::new)
+    }
+}
+
+#[test]
+fn round_trip() {
+    let sc = SynCell::new(vec![SynCell::new(1u8), SynCell::new(2)]);
+    let text = serde_json::to_string(&sc).unwrap();
+    assert_eq!(text, "[1,2]");
+    let other: SynCell<Vec<SynCell<u8>>> = serde_json::from_str(&text).unwrap();
+    assert_eq!(*other.borrow()[1].borrow(), 2);
+}
+
+#[cfg(not(syncell_unchecked))]
+#[test]
+fn mutably_borrowed() {
+    let sc = SynCell::new(0u8);
+    let _bw = sc.borrow_mut();
+    assert!(serde_json::to_string(&sc).is_err());
+}
+
+#[cfg(all(syncell_unchecked, not(unchecked)))]
+#[test]
+#[should_panic]
+fn mutably_borrowed_unchecked() {
+    let sc = SynCell::new(0u8);
+    let _bw = sc.borrow_mut();
+    let _ = serde_json::to_string(&sc);
+}
@@ -0,0 +1,201 @@
+//! Slice of cells with shared borrow states.
+//!
+//! Elements are grouped into stripes of about a cache line in size,
+//! and each stripe has a single state. Borrowing an element is
+//! therefore checked against the other elements of the same stripe:
+//! a mutable borrow of one of them conflicts with any borrow of another.
+
+#[cfg(not(unchecked))]
+use super::{BorrowError, BorrowMutError};
+use super::{Panicking, State, StateRef, SynRef, SynRefMut};
+use alloc::{boxed::Box, vec::Vec};
+use core::{cell::UnsafeCell, fmt, marker::PhantomData, mem, ptr::NonNull};
+
+/// Target size of the elements sharing a state.
+const STRIPE_BYTES: usize = 64;
+
+/// A fixed-size collection of values that can be borrowed individually,
+/// with the borrow state amortized across groups of neighboring elements.
+pub struct SynCellSlice<T> {
+    states: Box<[State]>,
+    values: Box<[UnsafeCell<T>]>,
+}
+
+unsafe impl<T: Send + Sync> Sync for SynCellSlice<T> {}
+
+impl<T> Default for SynCellSlice<T> {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl<T> From<Vec<T>> for SynCellSlice<T> {
+    fn from(values: Vec<T>) -> Self {
+        Self::new(values)
+    }
+}
+
+impl<T> FromIterator<T> for SynCellSlice<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+impl<T> fmt::Debug for SynCellSlice<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SynCellSlice")
+            .field("len", &self.values.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> SynCellSlice<T> {
+    /// Number of elements sharing a single borrow state.
+    pub const STRIPE_LEN: usize = {
+        let size = if mem::size_of::<T>() == 0 {
+            1
+        } else {
+            mem::size_of::<T>()
+        };
+        if size < STRIPE_BYTES {
+            STRIPE_BYTES / size
+        } else {
+            1
+        }
+    };
+
+    /// Create a new slice of cells.
+    pub fn new(values: Vec<T>) -> Self {
+        let num_stripes = values.len().div_ceil(Self::STRIPE_LEN);
+        Self {
+            states: (0..num_stripes).map(|_| State::new()).collect(),
+            values: values.into_iter().map(UnsafeCell::new).collect(),
+        }
+    }
+
+    /// Number of elements.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Check if there are no elements.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Convert into the values.
+    pub fn into_vec(self) -> Vec<T> {
+        debug_assert!(self.states.iter().all(State::is_unused));
+        self.values
+            .into_vec()
+            .into_iter()
+            .map(UnsafeCell::into_inner)
+            .collect()
+    }
+
+    /// Get a direct mutable reference to an element.
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn get_mut(&mut self, index: usize) -> &mut T {
+        debug_assert!(self.states[index / Self::STRIPE_LEN].is_unused());
+        self.values[index].get_mut()
+    }
+
+    /// Borrow an element immutably (can be shared).
+    ///
+    /// Panics if the index is out of bounds, or if an element
+    /// of the same stripe is already borrowed mutably.
+    #[track_caller]
+    pub fn borrow(&self, index: usize) -> SynRef<'_, T> {
+        let value = &self.values[index];
+        let state = &self.states[index / Self::STRIPE_LEN];
+        if let Err(e) = state.try_borrow() {
+            panic!("{}", e);
+        }
+        SynRef {
+            state: StateRef::new(state),
+            value: unsafe { NonNull::new_unchecked(value.get()) },
+            _marker: PhantomData,
+        }
+    }
+
+    /// Borrow an element immutably (can be shared), returning an error
+    /// if an element of the same stripe is already borrowed mutably.
+    ///
+    /// Panics if the index is out of bounds.
+    #[cfg(not(unchecked))]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn try_borrow(&self, index: usize) -> Result<SynRef<'_, T>, BorrowError> {
+        let value = &self.values[index];
+        let state = &self.states[index / Self::STRIPE_LEN];
+        state.try_borrow()?;
+        Ok(SynRef {
+            state: StateRef::new(state),
+            value: unsafe { NonNull::new_unchecked(value.get()) },
+            _marker: PhantomData,
+        })
+    }
+
+    /// Borrow an element mutably (exclusive).
+    ///
+    /// Panics if the index is out of bounds, or if any element
+    /// of the same stripe is already borrowed.
+    #[track_caller]
+    pub fn borrow_mut(&self, index: usize) -> SynRefMut<'_, T> {
+        let value = &self.values[index];
+        let state = &self.states[index / Self::STRIPE_LEN];
+        if let Err(e) = state.try_borrow_mut() {
+            panic!("{}", e);
+        }
+        SynRefMut {
+            state: StateRef::new(state),
+            value: unsafe { NonNull::new_unchecked(value.get()) },
+            panicking: Panicking::now(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Borrow an element mutably (exclusive), returning an error
+    /// if any element of the same stripe is already borrowed.
+    ///
+    /// Panics if the index is out of bounds.
+    #[cfg(not(unchecked))]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn try_borrow_mut(&self, index: usize) -> Result<SynRefMut<'_, T>, BorrowMutError> {
+        let value = &self.values[index];
+        let state = &self.states[index / Self::STRIPE_LEN];
+        state.try_borrow_mut()?;
+        Ok(SynRefMut {
+            state: StateRef::new(state),
+            value: unsafe { NonNull::new_unchecked(value.get()) },
+            panicking: Panicking::now(),
+            _marker: PhantomData,
+        })
+    }
+}
+
+#[cfg(not(unchecked))]
+#[test]
+fn stripes() {
+    let slice = (0..100u32).collect::<SynCellSlice<_>>();
+    assert_eq!(SynCellSlice::<u32>::STRIPE_LEN, 16);
+    assert_eq!(slice.states.len(), 7);
+    let mut w = slice.borrow_mut(3);
+    *w += 100;
+    assert!(slice.try_borrow(15).is_err());
+    assert_eq!(*slice.borrow(16), 16);
+    drop(w);
+    let r = slice.borrow(3);
+    assert!(slice.try_borrow_mut(4).is_err());
+    assert_eq!(*r + *slice.borrow(4), 107);
+    drop(r);
+    *slice.borrow_mut(99) = 0;
+    let values = slice.into_vec();
+    assert_eq!((values[3], values[99]), (103, 0));
+}
+
+#[test]
+fn stripe_len() {
+    assert_eq!(SynCellSlice::<()>::STRIPE_LEN, 64);
+    assert_eq!(SynCellSlice::<[u8; 100]>::STRIPE_LEN, 1);
+}
@@ -0,0 +1,597 @@
+//! Borrow state machine.
+//!
+//! The state is a single word, with the flags in the high bits,
+//! and the number of shared borrows in the low bits.
+//! All borrows are acquired with CAS loops, so a failed attempt
+//! never modifies the state, and can't cause false conflicts for others.
+//!
+//! While the value is borrowed mutably, the low bits count the extra
+//! mutable guards produced by splitting the borrow. For this to work,
+//! shared borrows never touch the word if `WRITE_BIT` is set.
+//!
+//! With "reentrancy" feature, the state also remembers the thread owning
+//! the mutable borrow. The owner is always cleared before `WRITE_BIT` is,
+//! so a thread can only see itself as the owner if it really is.
+
+#[cfg(feature = "metrics")]
+use super::metrics;
+#[cfg(feature = "debug-tracking")]
+use super::track;
+use super::{
+    atomic::{AtomicUsize, Ordering},
+    BorrowError, BorrowKind, BorrowMutError, BorrowState,
+};
+use core::{mem, ops};
+
+const WRITE_BIT: usize = 1 << (mem::size_of::<usize>() * 8 - 1);
+/// Set when there are threads parked on this state, waiting for it to be released.
+#[cfg_attr(any(loom, not(feature = "std")), allow(dead_code))]
+pub(crate) const PARKED_BIT: usize = WRITE_BIT >> 1;
+/// Set when there is an upgradable borrow.
+const UPGRADE_BIT: usize = WRITE_BIT >> 2;
+/// Set when a mutable borrow was released during a panic.
+/// Only used with "poison" feature.
+#[cfg_attr(not(feature = "poison"), allow(dead_code))]
+const POISON_BIT: usize = WRITE_BIT >> 3;
+/// Set when a blocking writer is waiting, so that the new blocking readers hold off.
+/// Only used by `SynRwCell` preferring writers.
+#[cfg_attr(any(loom, not(feature = "std")), allow(dead_code))]
+const WAITING_BIT: usize = WRITE_BIT >> 4;
+const READ_MASK: usize = WAITING_BIT - 1;
+/// Limit of shared borrows, leaving plenty of room before the count
+/// would overflow into the flags.
+const MAX_READERS: usize = READ_MASK >> 1;
+/// All the bits signifying that the value is borrowed.
+const BORROW_MASK: usize = WRITE_BIT | UPGRADE_BIT | READ_MASK;
+
+#[cold]
+fn too_many_readers() -> ! {
+    panic!("Too many shared borrows of SynCell!")
+}
+
+/// Unique non-zero identifier of the current thread.
+/// Unlike the addresses of thread locals, these are never reused.
+#[cfg(all(feature = "std", not(loom)))]
+pub(crate) fn current_thread() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(1);
+    std::thread_local!(static ID: usize = NEXT.fetch_add(1, Ordering::Relaxed));
+    ID.with(|id| *id)
+}
+
+/// Whether the thread was panicking when a mutable borrow was taken,
+/// so that only a panic happening during the borrow poisons the cell.
+#[derive(Clone, Copy)]
+pub(crate) struct Panicking {
+    #[cfg(feature = "poison")]
+    at_borrow: bool,
+}
+
+impl Panicking {
+    #[inline]
+    pub fn now() -> Self {
+        Self {
+            #[cfg(feature = "poison")]
+            at_borrow: std::thread::panicking(),
+        }
+    }
+}
+
+/// Reference to the state, held by the guards.
+#[derive(Clone, Copy)]
+pub(crate) struct StateRef<'a>(&'a State);
+
+impl<'a> StateRef<'a> {
+    #[inline]
+    pub fn new(state: &'a State) -> Self {
+        Self(state)
+    }
+}
+
+impl ops::Deref for StateRef<'_> {
+    type Target = State;
+    #[inline]
+    fn deref(&self) -> &State {
+        self.0
+    }
+}
+
+pub(crate) struct State {
+    bits: AtomicUsize,
+    #[cfg(feature = "debug-tracking")]
+    sites: track::Sites,
+    #[cfg(feature = "metrics")]
+    counters: metrics::Counters,
+    #[cfg(feature = "version")]
+    version: AtomicUsize,
+    #[cfg(all(feature = "reentrancy", not(loom)))]
+    owner: AtomicUsize,
+}
+
+impl State {
+    #[cfg(not(loom))]
+    pub const fn new() -> Self {
+        Self {
+            bits: AtomicUsize::new(0),
+            #[cfg(feature = "debug-tracking")]
+            sites: track::Sites::new(),
+            #[cfg(feature = "metrics")]
+            counters: metrics::Counters::new(),
+            #[cfg(feature = "version")]
+            version: AtomicUsize::new(0),
+            #[cfg(all(feature = "reentrancy", not(loom)))]
+            owner: AtomicUsize::new(0),
+        }
+    }
+
+    /// Loom atomics can't be created in const context.
+    #[cfg(loom)]
+    pub fn new() -> Self {
+        Self {
+            bits: AtomicUsize::new(0),
+            #[cfg(feature = "debug-tracking")]
+            sites: track::Sites::new(),
+            #[cfg(feature = "metrics")]
+            counters: metrics::Counters::new(),
+            #[cfg(feature = "version")]
+            version: AtomicUsize::new(0),
+        }
+    }
+
+    /// Check that there are no borrows.
+    pub fn is_unused(&self) -> bool {
+        self.bits.load(Ordering::Acquire) & BORROW_MASK == 0
+    }
+
+    pub fn borrow_state(&self) -> BorrowState {
+        let bits = self.bits.load(Ordering::Acquire);
+        if bits & WRITE_BIT != 0 {
+            BorrowState::Writing
+        } else if bits & (UPGRADE_BIT | READ_MASK) != 0 {
+            BorrowState::Reading {
+                readers: bits & READ_MASK,
+                upgradable: bits & UPGRADE_BIT != 0,
+            }
+        } else {
+            BorrowState::Unused
+        }
+    }
+
+    /// Forget all the borrows, waking up anybody waiting for them.
+    pub fn force_reset(&self) {
+        self.forget(BorrowKind::Upgradable);
+        self.forget(BorrowKind::Exclusive);
+        self.disown();
+        let old = self.bits.fetch_and(!BORROW_MASK, Ordering::Release);
+        self.released(old);
+    }
+
+    /// Process the old state of a released borrow.
+    #[inline]
+    fn released(&self, old: usize) {
+        #[cfg(all(feature = "std", not(loom)))]
+        if old & PARKED_BIT != 0 {
+            super::park::unpark_all(&self.bits);
+        }
+        #[cfg(not(all(feature = "std", not(loom))))]
+        let _ = old;
+    }
+
+    /// Remember the caller as the holder of a new upgradable or mutable borrow.
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    fn record(&self, kind: BorrowKind) {
+        #[cfg(feature = "debug-tracking")]
+        self.sites.record(kind);
+        #[cfg(not(feature = "debug-tracking"))]
+        let _ = kind;
+    }
+
+    /// Forget the holder of an upgradable or mutable borrow, before it's released.
+    #[inline]
+    fn forget(&self, kind: BorrowKind) {
+        #[cfg(feature = "debug-tracking")]
+        self.sites.clear(kind);
+        #[cfg(not(feature = "debug-tracking"))]
+        let _ = kind;
+    }
+
+    /// Remember the current thread as the owner of the mutable borrow.
+    #[inline]
+    fn claim(&self) {
+        #[cfg(all(feature = "reentrancy", not(loom)))]
+        self.owner.store(current_thread(), Ordering::Relaxed);
+    }
+
+    /// Forget the owner, has to be done before `WRITE_BIT` is cleared.
+    #[inline]
+    fn disown(&self) {
+        #[cfg(all(feature = "reentrancy", not(loom)))]
+        self.owner.store(0, Ordering::Relaxed);
+    }
+
+    /// Check if the mutable borrow is owned by the current thread.
+    #[cfg(all(feature = "reentrancy", not(loom)))]
+    fn is_owned(&self) -> bool {
+        self.owner.load(Ordering::Relaxed) == current_thread()
+    }
+
+    /// Mark the value as possibly modified.
+    /// Has to be done before the exclusive borrow is released.
+    #[inline]
+    pub fn modified(&self) {
+        #[cfg(feature = "version")]
+        self.version.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "version")]
+    pub fn version(&self) -> usize {
+        self.version.load(Ordering::Relaxed)
+    }
+
+    /// Count a successful borrow, with the given number of shared borrows after it.
+    #[inline]
+    fn borrowed(&self, readers: usize) {
+        #[cfg(feature = "metrics")]
+        self.counters.borrowed(readers);
+        #[cfg(not(feature = "metrics"))]
+        let _ = readers;
+    }
+
+    /// Count a failed borrow attempt.
+    #[inline]
+    fn conflicted(&self) {
+        #[cfg(feature = "metrics")]
+        self.counters.conflicted();
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> metrics::Metrics {
+        self.counters.get()
+    }
+
+    fn borrow_error(&self) -> BorrowError {
+        BorrowError {
+            #[cfg(feature = "debug-tracking")]
+            site: self.sites.get(BorrowKind::Exclusive),
+            #[cfg(all(feature = "reentrancy", not(loom)))]
+            reentrant: self.is_owned(),
+            _private: (),
+        }
+    }
+
+    pub fn borrow_mut_error(&self, conflict: BorrowKind) -> BorrowMutError {
+        BorrowMutError {
+            #[cfg(feature = "debug-tracking")]
+            site: self.sites.get(conflict),
+            #[cfg(all(feature = "reentrancy", not(loom)))]
+            reentrant: match conflict {
+                BorrowKind::Exclusive => Some(self.is_owned()),
+                BorrowKind::Shared | BorrowKind::Upgradable => None,
+            },
+            conflict,
+        }
+    }
+
+    pub fn try_borrow(&self) -> Result<(), BorrowError> {
+        let mut cur = self.bits.load(Ordering::Relaxed);
+        loop {
+            if cur & WRITE_BIT != 0 {
+                self.conflicted();
+                return Err(self.borrow_error());
+            }
+            if cur & READ_MASK >= MAX_READERS {
+                too_many_readers();
+            }
+            match self.bits.compare_exchange_weak(
+                cur,
+                cur + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(actual) => cur = actual,
+            }
+        }
+        self.borrowed((cur & READ_MASK) + 1);
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn try_borrow_mut(&self) -> Result<(), BorrowMutError> {
+        let mut cur = self.bits.load(Ordering::Relaxed);
+        loop {
+            if cur & BORROW_MASK != 0 {
+                self.conflicted();
+                return Err(self.borrow_mut_error(if cur & WRITE_BIT != 0 {
+                    BorrowKind::Exclusive
+                } else if cur & READ_MASK != 0 {
+                    BorrowKind::Shared
+                } else {
+                    BorrowKind::Upgradable
+                }));
+            }
+            match self.bits.compare_exchange_weak(
+                cur,
+                cur | WRITE_BIT,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(actual) => cur = actual,
+            }
+        }
+        self.claim();
+        self.borrowed(0);
+        self.record(BorrowKind::Exclusive);
+        Ok(())
+    }
+
+    /// Borrow mutably, or add another exclusive guard
+    /// if the current thread already owns the mutable borrow.
+    #[cfg(all(feature = "reentrancy", not(loom)))]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn try_borrow_mut_reentrant(&self) -> Result<(), BorrowMutError> {
+        let mut cur = self.bits.load(Ordering::Relaxed);
+        loop {
+            if cur & WRITE_BIT == 0 || !self.is_owned() {
+                return self.try_borrow_mut();
+            }
+            match self.bits.compare_exchange_weak(
+                cur,
+                cur + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(actual) => cur = actual,
+            }
+        }
+        // The borrow could have been released by a guard on another thread,
+        // and taken again, before the update went through.
+        if !self.is_owned() {
+            self.release_exclusive_intact();
+            return self.try_borrow_mut();
+        }
+        self.borrowed(0);
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn try_borrow_upgradable(&self) -> Result<(), BorrowMutError> {
+        let mut cur = self.bits.load(Ordering::Relaxed);
+        loop {
+            if cur & (WRITE_BIT | UPGRADE_BIT) != 0 {
+                self.conflicted();
+                return Err(self.borrow_mut_error(if cur & UPGRADE_BIT != 0 {
+                    BorrowKind::Upgradable
+                } else {
+                    BorrowKind::Exclusive
+                }));
+            }
+            match self.bits.compare_exchange_weak(
+                cur,
+                cur | UPGRADE_BIT,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(actual) => cur = actual,
+            }
+        }
+        self.borrowed(cur & READ_MASK);
+        self.record(BorrowKind::Upgradable);
+        Ok(())
+    }
+
+    /// Turn an upgradable borrow into an exclusive one,
+    /// if there are no shared borrows.
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn try_upgrade(&self) -> bool {
+        let mut cur = self.bits.load(Ordering::Relaxed);
+        loop {
+            if cur & (WRITE_BIT | READ_MASK) != 0 {
+                self.conflicted();
+                return false;
+            }
+            let new = (cur & !UPGRADE_BIT) | WRITE_BIT;
+            match self
+                .bits
+                .compare_exchange_weak(cur, new, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(actual) => cur = actual,
+            }
+        }
+        self.claim();
+        self.borrowed(0);
+        self.forget(BorrowKind::Upgradable);
+        self.record(BorrowKind::Exclusive);
+        true
+    }
+
+    /// Add another shared guard to an existing shared borrow.
+    pub fn clone_shared(&self) {
+        // Like with `Arc`, the overflow is checked after the fact,
+        // since there is a lot of room left.
+        let old = self.bits.fetch_add(1, Ordering::Relaxed);
+        if old & READ_MASK >= MAX_READERS {
+            self.release_shared();
+            too_many_readers();
+        }
+        self.borrowed((old & READ_MASK) + 1);
+    }
+
+    /// Add another exclusive guard to an existing exclusive borrow.
+    pub fn split_exclusive(&self) {
+        self.bits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Turn an exclusive borrow into a shared one.
+    /// Returns `false` without touching anything if the borrow is split.
+    pub fn try_downgrade(&self) -> bool {
+        if self.bits.load(Ordering::Relaxed) & READ_MASK != 0 {
+            return false;
+        }
+        self.modified();
+        self.forget(BorrowKind::Exclusive);
+        self.disown();
+        // Clear the write bit and add a reader in one go,
+        // unless a reentrant borrow has split it meanwhile.
+        let old = match self
+            .bits
+            .fetch_update(Ordering::Release, Ordering::Relaxed, |s| {
+                (s & READ_MASK == 0).then(|| s - (WRITE_BIT - 1))
+            }) {
+            Ok(old) => old,
+            Err(_) => return false,
+        };
+        self.released(old);
+        true
+    }
+
+    /// Turn an upgradable borrow into a shared one.
+    pub fn downgrade_upgradable(&self) {
+        self.forget(BorrowKind::Upgradable);
+        // Clear the upgrade bit and add a reader in one go.
+        let old = self.bits.fetch_sub(UPGRADE_BIT - 1, Ordering::Release);
+        self.released(old);
+    }
+
+    pub fn release_shared(&self) {
+        let old = self.bits.fetch_sub(1, Ordering::Release);
+        self.released(old);
+    }
+
+    pub fn release_exclusive(&self, panicking: Panicking) {
+        self.modified();
+        #[cfg(feature = "poison")]
+        if std::thread::panicking() && !panicking.at_borrow {
+            self.bits.fetch_or(POISON_BIT, Ordering::Relaxed);
+        }
+        self.release_exclusive_intact();
+        #[cfg(not(feature = "poison"))]
+        let _ = panicking;
+    }
+
+    /// Release an exclusive borrow that didn't modify the value, so no poisoning.
+    pub fn release_exclusive_intact(&self) {
+        // If there are no other guards, they can't appear, and we can
+        // release quickly. Otherwise, we need to figure out who is the last.
+        // Reentrant borrows can add guards at any time, so they go the long way.
+        let old = if !cfg!(all(feature = "reentrancy", not(loom)))
+            && self.bits.load(Ordering::Relaxed) & READ_MASK == 0
+        {
+            self.forget(BorrowKind::Exclusive);
+            self.disown();
+            self.bits.fetch_and(!WRITE_BIT, Ordering::Release)
+        } else {
+            let old = self
+                .bits
+                .fetch_update(Ordering::Release, Ordering::Relaxed, |s| {
+                    Some(if s & READ_MASK != 0 {
+                        s - 1
+                    } else {
+                        self.forget(BorrowKind::Exclusive);
+                        self.disown();
+                        s & !WRITE_BIT
+                    })
+                })
+                .unwrap();
+            if old & READ_MASK != 0 {
+                return;
+            }
+            old
+        };
+        self.released(old);
+    }
+
+    pub fn release_upgradable(&self) {
+        self.forget(BorrowKind::Upgradable);
+        let old = self.bits.fetch_and(!UPGRADE_BIT, Ordering::Release);
+        self.released(old);
+    }
+
+    #[cfg(feature = "poison")]
+    pub fn is_poisoned(&self) -> bool {
+        self.bits.load(Ordering::Relaxed) & POISON_BIT != 0
+    }
+
+    #[cfg(feature = "poison")]
+    pub fn clear_poison(&self) {
+        self.bits.fetch_and(!POISON_BIT, Ordering::Relaxed);
+    }
+
+    /// Check if a borrow of the given kind is blocked by the state.
+    #[cfg(all(feature = "std", not(loom)))]
+    fn is_blocked(kind: BorrowKind, state: usize) -> bool {
+        let mask = match kind {
+            BorrowKind::Shared => WRITE_BIT,
+            BorrowKind::Upgradable => WRITE_BIT | UPGRADE_BIT,
+            BorrowKind::Exclusive => BORROW_MASK,
+        };
+        state & mask != 0
+    }
+
+    /// Park the current thread until a borrow of the given kind could be possible,
+    /// or the deadline is reached.
+    #[cfg(all(feature = "std", not(loom)))]
+    pub fn park(&self, kind: BorrowKind, deadline: Option<std::time::Instant>) {
+        super::park::park(&self.bits, |s| Self::is_blocked(kind, s), deadline);
+    }
+
+    /// Park the current thread until there are no mutable borrows or waiting writers.
+    #[cfg(all(feature = "std", not(loom)))]
+    pub fn park_behind_writers(&self) {
+        super::park::park(&self.bits, |s| s & (WRITE_BIT | WAITING_BIT) != 0, None);
+    }
+
+    #[cfg(all(feature = "std", not(loom)))]
+    pub fn is_writer_waiting(&self) -> bool {
+        self.bits.load(Ordering::Relaxed) & WAITING_BIT != 0
+    }
+
+    #[cfg(all(feature = "std", not(loom)))]
+    pub fn set_writer_waiting(&self) {
+        self.bits.fetch_or(WAITING_BIT, Ordering::Relaxed);
+    }
+
+    /// Clear the waiting writer, waking up the readers behind it.
+    #[cfg(all(feature = "std", not(loom)))]
+    pub fn clear_writer_waiting(&self) {
+        let old = self.bits.fetch_and(!WAITING_BIT, Ordering::Relaxed);
+        if old & WAITING_BIT != 0 {
+            self.released(old);
+        }
+    }
+
+    /// Register the task waker to be woken up when a borrow of the given kind
+    /// could be possible. Returns `false` if it's possible already.
+    #[cfg(all(feature = "async", not(loom)))]
+    pub fn register(&self, kind: BorrowKind, waker: &std::task::Waker) -> bool {
+        super::park::register(&self.bits, |s| Self::is_blocked(kind, s), waker)
+    }
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        debug_assert!(
+            self.is_unused(),
+            "SynCell is dropped while borrowed, a guard must have been leaked!"
+        );
+    }
+}
+
+#[test]
+fn reader_overflow() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let state = State::new();
+    state.bits.store(MAX_READERS - 1, Ordering::Relaxed);
+    state.try_borrow().unwrap();
+    assert!(catch_unwind(AssertUnwindSafe(|| state.try_borrow())).is_err());
+    assert!(catch_unwind(AssertUnwindSafe(|| state.clone_shared())).is_err());
+    // The failed attempts leave the count intact.
+    assert_eq!(state.bits.load(Ordering::Relaxed), MAX_READERS);
+    state.release_shared();
+    state.try_borrow().unwrap();
+    state.force_reset();
+}
@@ -0,0 +1,78 @@
+//! Tracking of the active borrows with "debug-tracking" feature.
+//!
+//! Each state remembers where its live upgradable and mutable borrows were made,
+//! so that the conflict errors can point to the culprit. There is at most one
+//! of each, recorded after it's taken, and cleared before it's released.
+//! Shared borrows can be many, and are not tracked.
+//! This is kept out of the atomic state, and only touched around
+//! the state transitions.
+
+use super::BorrowKind;
+use std::{
+    fmt,
+    panic::Location,
+    sync::{Mutex, MutexGuard, PoisonError},
+    thread::{self, Thread},
+};
+
+/// Location and thread of a borrow.
+#[derive(Clone, Debug)]
+pub(crate) struct Site {
+    location: &'static Location<'static>,
+    thread: Thread,
+}
+
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.thread.name() {
+            Some(name) => write!(f, "{} by thread '{}'", self.location, name),
+            None => write!(f, "{} by thread {:?}", self.location, self.thread.id()),
+        }
+    }
+}
+
+pub(crate) struct Sites {
+    upgradable: Mutex<Option<Site>>,
+    exclusive: Mutex<Option<Site>>,
+}
+
+impl Sites {
+    pub const fn new() -> Self {
+        Self {
+            upgradable: Mutex::new(None),
+            exclusive: Mutex::new(None),
+        }
+    }
+
+    fn slot(&self, kind: BorrowKind) -> MutexGuard<'_, Option<Site>> {
+        match kind {
+            BorrowKind::Shared => unreachable!("Shared borrows are not tracked"),
+            BorrowKind::Upgradable => &self.upgradable,
+            BorrowKind::Exclusive => &self.exclusive,
+        }
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Record the caller as the live borrow of this kind.
+    #[track_caller]
+    pub fn record(&self, kind: BorrowKind) {
+        *self.slot(kind) = Some(Site {
+            location: Location::caller(),
+            thread: thread::current(),
+        });
+    }
+
+    /// Forget the borrow of this kind, which is being released.
+    pub fn clear(&self, kind: BorrowKind) {
+        *self.slot(kind) = None;
+    }
+
+    /// Get the live borrow of this kind, if it's known.
+    pub fn get(&self, kind: BorrowKind) -> Option<Site> {
+        match kind {
+            BorrowKind::Shared => None,
+            BorrowKind::Upgradable | BorrowKind::Exclusive => self.slot(kind).clone(),
+        }
+    }
+}
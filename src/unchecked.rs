@@ -0,0 +1,172 @@
+//! Borrow state without the checks, used with `--cfg syncell_unchecked`
+//! in the builds without debug assertions.
+//!
+//! Every borrow succeeds right away, and releasing does nothing,
+//! so the guards boil down to the raw pointers to the value.
+//! Only the panicking borrows are built on top of it:
+//! the debug builds show that they never conflict.
+//! With "version" feature, the state keeps the counter,
+//! and the guards point to it.
+
+#[cfg(feature = "version")]
+use super::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "metrics")]
+use super::metrics;
+use super::{state::Panicking, BorrowError, BorrowKind, BorrowMutError};
+use core::{marker::PhantomData, ops};
+
+pub(crate) struct State {
+    #[cfg(feature = "version")]
+    version: AtomicUsize,
+}
+
+/// Without the version, there is nothing to point to,
+/// so the guards only keep the lifetime.
+#[derive(Clone, Copy)]
+pub(crate) struct StateRef<'a> {
+    #[cfg(feature = "version")]
+    state: &'a State,
+    _marker: PhantomData<&'a State>,
+}
+
+impl<'a> StateRef<'a> {
+    #[inline]
+    pub fn new(state: &'a State) -> Self {
+        #[cfg(not(feature = "version"))]
+        let _ = state;
+        Self {
+            #[cfg(feature = "version")]
+            state,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl ops::Deref for StateRef<'_> {
+    type Target = State;
+    #[inline]
+    fn deref(&self) -> &State {
+        #[cfg(feature = "version")]
+        return self.state;
+        #[cfg(not(feature = "version"))]
+        &State {}
+    }
+}
+
+#[cfg(not(feature = "version"))]
+const _: () = {
+    use core::mem::size_of;
+    assert!(size_of::<State>() == 0 && size_of::<StateRef>() == 0);
+    assert!(size_of::<super::SynRef<u8>>() == size_of::<usize>());
+};
+
+impl State {
+    pub const fn new() -> Self {
+        Self {
+            #[cfg(feature = "version")]
+            version: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn is_unused(&self) -> bool {
+        true
+    }
+
+    pub fn force_reset(&self) {}
+
+    /// Count the mutable borrow, just like the checked state does.
+    #[inline]
+    pub fn modified(&self) {
+        #[cfg(feature = "version")]
+        self.version.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "version")]
+    pub fn version(&self) -> usize {
+        self.version.load(Ordering::Relaxed)
+    }
+
+    /// Nothing is counted without the borrow state.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> metrics::Metrics {
+        metrics::Metrics::default()
+    }
+
+    pub fn borrow_mut_error(&self, conflict: BorrowKind) -> BorrowMutError {
+        BorrowMutError {
+            #[cfg(feature = "debug-tracking")]
+            site: None,
+            #[cfg(feature = "reentrancy")]
+            reentrant: None,
+            conflict,
+        }
+    }
+
+    #[inline]
+    pub fn try_borrow(&self) -> Result<(), BorrowError> {
+        Ok(())
+    }
+
+    #[inline]
+    pub fn try_borrow_mut(&self) -> Result<(), BorrowMutError> {
+        Ok(())
+    }
+
+    #[cfg(feature = "reentrancy")]
+    #[inline]
+    pub fn try_borrow_mut_reentrant(&self) -> Result<(), BorrowMutError> {
+        Ok(())
+    }
+
+    #[inline]
+    pub fn try_borrow_upgradable(&self) -> Result<(), BorrowMutError> {
+        Ok(())
+    }
+
+    #[inline]
+    pub fn try_upgrade(&self) -> bool {
+        true
+    }
+
+    pub fn clone_shared(&self) {}
+
+    pub fn split_exclusive(&self) {}
+
+    pub fn try_downgrade(&self) -> bool {
+        self.modified();
+        true
+    }
+
+    pub fn downgrade_upgradable(&self) {}
+
+    pub fn release_shared(&self) {}
+
+    pub fn release_exclusive(&self, _panicking: Panicking) {
+        self.modified();
+    }
+
+    pub fn release_exclusive_intact(&self) {}
+
+    pub fn release_upgradable(&self) {}
+
+    #[cfg(feature = "poison")]
+    pub fn is_poisoned(&self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "poison")]
+    pub fn clear_poison(&self) {}
+}
+
+#[test]
+fn unchecked() {
+    let sc = super::SynCell::new(0u8);
+    *sc.borrow_mut() = 1;
+    assert_eq!(format!("{:?}", sc), "SynCell { value: <unchecked> }");
+    #[cfg(feature = "serde")]
+    assert_eq!(serde_json::to_string(&sc).unwrap(), "1");
+    #[cfg(feature = "version")]
+    assert_eq!(sc.version(), 1);
+    #[cfg(feature = "metrics")]
+    assert_eq!(sc.metrics(), super::Metrics::default());
+}
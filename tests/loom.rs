@@ -0,0 +1,181 @@
+//! Loom models of the state transitions.
+//!
+//! Run with `RUSTFLAGS="--cfg loom" cargo test --features loom --test loom --release`.
+#![cfg(loom)]
+
+use loom::{cell::UnsafeCell, sync::Arc, thread};
+use syncell::{SynCell, SynRefMut, SynRefUpgradable};
+
+// The value is a Loom cell, so that any unsynchronized access is detected.
+type Cell = SynCell<UnsafeCell<usize>>;
+
+fn read(cell: &Cell) -> Option<usize> {
+    cell.try_borrow()
+        .ok()
+        .map(|r| r.with(|ptr| unsafe { *ptr }))
+}
+
+#[test]
+fn downgrade() {
+    loom::model(|| {
+        let cell = Arc::new(Cell::new(UnsafeCell::new(0)));
+        let other = Arc::clone(&cell);
+        let jh = thread::spawn(move || read(&other));
+
+        if let Ok(w) = cell.try_borrow_mut() {
+            w.with_mut(|ptr| unsafe { *ptr = 1 });
+            let r = SynRefMut::downgrade(w);
+            assert!(cell.try_borrow_mut().is_err());
+            assert_eq!(r.with(|ptr| unsafe { *ptr }), 1);
+        }
+
+        if let Some(value) = jh.join().unwrap() {
+            assert!(value == 0 || value == 1);
+        }
+    });
+}
+
+#[test]
+fn upgrade() {
+    loom::model(|| {
+        let cell = Arc::new(Cell::new(UnsafeCell::new(0)));
+        let other = Arc::clone(&cell);
+        let jh = thread::spawn(move || read(&other));
+
+        let u = cell.borrow_upgradable();
+        if let Ok(w) = SynRefUpgradable::try_upgrade(u) {
+            w.with_mut(|ptr| unsafe { *ptr = 1 });
+        }
+
+        if let Some(value) = jh.join().unwrap() {
+            assert!(value == 0 || value == 1);
+        }
+    });
+}
+
+#[test]
+fn upgrade_race() {
+    loom::model(|| {
+        let cell = Arc::new(Cell::new(UnsafeCell::new(0)));
+        let other = Arc::clone(&cell);
+        let jh = thread::spawn(move || {
+            if let Ok(u) = other.try_borrow_upgradable() {
+                if let Ok(w) = SynRefUpgradable::try_upgrade(u) {
+                    w.with_mut(|ptr| unsafe { *ptr += 1 });
+                }
+            }
+        });
+
+        if let Ok(w) = cell.try_borrow_mut() {
+            w.with_mut(|ptr| unsafe { *ptr += 1 });
+        }
+
+        jh.join().unwrap();
+    });
+}
+
+#[test]
+fn split() {
+    loom::model(|| {
+        let cell = Arc::new(SynCell::new([UnsafeCell::new(0), UnsafeCell::new(0)]));
+        let other = Arc::clone(&cell);
+        let jh = thread::spawn(move || {
+            other
+                .try_borrow()
+                .ok()
+                .map(|r| r[0].with(|ptr| unsafe { *ptr }) + r[1].with(|ptr| unsafe { *ptr }))
+        });
+
+        if let Ok(w) = cell.try_borrow_mut() {
+            let (a, b) = SynRefMut::split_at(SynRefMut::map(w, |v| &mut v[..]), 1);
+            b[0].with_mut(|ptr| unsafe { *ptr = 1 });
+            drop(b);
+            a[0].with_mut(|ptr| unsafe { *ptr = 1 });
+        }
+
+        if let Some(value) = jh.join().unwrap() {
+            assert!(value == 0 || value == 2);
+        }
+    });
+}
+
+#[test]
+fn failed_writer() {
+    loom::model(|| {
+        let cell = Arc::new(Cell::new(UnsafeCell::new(0)));
+        let r = cell.borrow();
+        let writer = Arc::clone(&cell);
+        let jh1 = thread::spawn(move || assert!(writer.try_borrow_mut().is_err()));
+        let reader = Arc::clone(&cell);
+        // A failed writer must never make a reader fail.
+        let jh2 = thread::spawn(move || assert_eq!(read(&reader), Some(0)));
+        jh1.join().unwrap();
+        jh2.join().unwrap();
+        drop(r);
+        assert!(cell.try_borrow_mut().is_ok());
+    });
+}
+
+#[test]
+fn failed_upgradable() {
+    loom::model(|| {
+        let cell = Arc::new(Cell::new(UnsafeCell::new(0)));
+        let w = cell.borrow_mut();
+        let other = Arc::clone(&cell);
+        let jh = thread::spawn(move || other.try_borrow_upgradable().is_ok());
+        drop(w);
+        let ok = cell.try_borrow_mut().is_ok();
+        // We can only fail because of a successful upgradable borrow.
+        assert!(ok || jh.join().unwrap());
+    });
+}
+
+#[test]
+fn readers_writer() {
+    loom::model(|| {
+        let cell = Arc::new(Cell::new(UnsafeCell::new(0)));
+        let readers = (0..2)
+            .map(|_| {
+                let other = Arc::clone(&cell);
+                thread::spawn(move || read(&other))
+            })
+            .collect::<Vec<_>>();
+
+        let written = match cell.try_borrow_mut() {
+            Ok(w) => {
+                w.with_mut(|ptr| unsafe { *ptr = 1 });
+                true
+            }
+            Err(_) => false,
+        };
+
+        for jh in readers {
+            match jh.join().unwrap() {
+                Some(value) => assert!(value == 0 || value == 1),
+                // Readers can only fail because of a successful writer.
+                None => assert!(written),
+            }
+        }
+    });
+}
+
+#[test]
+fn writers() {
+    loom::model(|| {
+        let cell = Arc::new(Cell::new(UnsafeCell::new(0)));
+        let other = Arc::clone(&cell);
+        let jh = thread::spawn(move || {
+            if let Ok(w) = other.try_borrow_mut() {
+                w.with_mut(|ptr| unsafe { *ptr += 1 });
+            }
+        });
+
+        if let Ok(w) = cell.try_borrow_mut() {
+            w.with_mut(|ptr| unsafe { *ptr += 1 });
+        }
+
+        jh.join().unwrap();
+        let value = read(&cell).unwrap();
+        assert!(value == 1 || value == 2);
+    });
+}